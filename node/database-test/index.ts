@@ -14,6 +14,7 @@ let resolve = (_ptr: number, _length: number) => {};
 const externs = {
   ctx: {
     resolve: (ptr: number, length: number) => resolve(ptr, length),
+    now: () => performance.now(),
   },
   console: {
     log: (ptr: number, length: number) => logger(ptr, length),
@@ -41,12 +42,34 @@ WebAssembly.instantiate(binary.buffer, { ...externs })
       memory: WebAssembly.Memory;
       alloc(capacity: number): number;
       // dealloc(ptr: number, capacity: number): void;
-      init(ptr: number): void;
-      persist(): void;
-      updateUsers(ptr: number): void;
-      updateGames(ptr: number): void;
-      filterGames(ptr0: number, ptr1: number): void;
-      getFullJson(): number;
+      open(ptr: number): number;
+      fork(handle: number): number;
+      close(handle: number): void;
+      persist(handle: number): void;
+      updateUsers(handle: number, ptr: number): void;
+      updateGames(handle: number, ptr: number): void;
+      deleteGames(handle: number, ptr: number): void;
+      importSteamHistogram(handle: number, id: number, ptr: number): void;
+      importCsv(handle: number, ptr: number, mappingPtr: number): void;
+      beginIngest(handle: number): number;
+      feedIngest(ingest: number, ptr: number): void;
+      finishIngest(ingest: number): void;
+      abortIngest(ingest: number): void;
+      begin(handle: number): void;
+      commit(handle: number): void;
+      rollback(handle: number): void;
+      filterGames(handle: number, ptr0: number, ptr1: number, ptr2: number): void;
+      explainGames(handle: number, ptr0: number, ptr1: number, ptr2: number): void;
+      exportGames(handle: number, ptr0: number, ptr1: number, ptr2: number, optionsPtr: number): void;
+      prepareQuery(ptr0: number, ptr1: number, ptr2: number): number;
+      runQuery(handle: number, query: number, ptr: number): void;
+      releaseQuery(query: number): void;
+      getFullJson(handle: number): number;
+      getMetrics(handle: number, reset: number): void;
+      setCacheCapacity(handle: number, capacity: number): void;
+      compactTags(handle: number): void;
+      renameTag(handle: number, ptr: number): void;
+      mergeTags(handle: number, ptr: number): void;
     }
     const program = (instance.exports as unknown) as Program;
     // program.memory.grow(10);
@@ -64,34 +87,37 @@ WebAssembly.instantiate(binary.buffer, { ...externs })
         case 1:
           console.log(Buffer.from(program.memory.buffer, val[1], val[2]).toString('utf-8'));
           break;
+        case 2:
+          console.error(val[1], Buffer.from(program.memory.buffer, val[2], val[3]).toString('utf-8'));
+          break;
       }
     };
 
-    const init = (snapshot: string) => {
+    const open = (snapshot: string) => {
       const { pointer } = writeSlice(Buffer.from(snapshot, 'base64'));
-      program.init(pointer);
+      return program.open(pointer);
     };
 
-    const persist = () => program.persist();
+    const persist = (handle: number) => program.persist(handle);
 
-    const updateUsers = (users: User[]) => {
+    const updateUsers = (handle: number, users: User[]) => {
       const { pointer } = writeSlice(Buffer.from(JSON.stringify(users)));
-      program.updateUsers(pointer);
+      program.updateUsers(handle, pointer);
     };
 
-    const updateGames = (games: GameInput[]) => {
+    const updateGames = (handle: number, games: GameInput[]) => {
       const { pointer } = writeSlice(Buffer.from(JSON.stringify(games)));
-      program.updateGames(pointer);
+      program.updateGames(handle, pointer);
     };
 
-    const getFullJson = () => program.getFullJson();
+    const getFullJson = (handle: number) => program.getFullJson(handle);
 
     // AAAAAAAAAAACAAAAAAAAAAAAAAAAAAAAAwAAAAAAAABmb28BAAAAAQAAAAMAAAAAAAAAYmFy
     if (true) {
-      init('AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA');
+      const db = open('');
       await Promise.resolve();
-      // updateUsers(['baz', 'quz', 'foo', 'bar'].map((name, id) => ({ id, name })));
-      updateGames([
+      // updateUsers(db, ['baz', 'quz', 'foo', 'bar'].map((name, id) => ({ id, name })));
+      updateGames(db, [
         { id: 0, name: 'hoge', releaseDate: 20000, recomendations: null, tags: ['Hoge', 'Fuga', 'タグ', '恐竜'] },
         { id: 1, name: 'geqgwq', releaseDate: 20000, recomendations: null, tags: ['Hogeaaa', 'Fuga', 'タグ', '恐竜'] },
         { id: 2, name: 'gwqhwq', releaseDate: 20000, recomendations: null, tags: ['Hoge', 'タグううう', '恐竜'] },
@@ -101,14 +127,15 @@ WebAssembly.instantiate(binary.buffer, { ...externs })
       const a = createIdQuery(1, 0, [0]);
       const d = pointerList([a.pointer]);
       const b = writeSlice(Buffer.from(''));
-      program.filterGames(d.pointer, b.pointer);
-      getFullJson();
-      persist();
+      const s = writeSlice(Buffer.from(''));
+      program.filterGames(db, d.pointer, b.pointer, s.pointer);
+      getFullJson(db);
+      persist(db);
     } else {
-      init(
+      const db = open(
         'AAAAAAAAAAAEAAAAAAAAAAIAAAACAAAABgAAAAAAAABnd3Fod3EBBAAAAAAAAAAAAAAAAQAAAAQAAAADAAAAASBOAAAAAAAAAAAAAAAEAAAAAAAAAGhvZ2UBBAAAAAAAAAAAAAAAAQAAAAIAAAADAAAAASBOAAAAAQAAAAEAAAAGAAAAAAAAAGdlcWd3cQEEAAAAAAAAAAAAAAABAAAAAgAAAAMAAAABIE4AAAADAAAAAwAAAAUAAAAAAAAAZ3dxaGUBBAAAAAAAAAAAAAAAAQAAAAIAAAAFAAAAASBOAAAABgAAAAAAAAACAAAABgAAAAAAAADjgr/jgrAFAAAADAAAAAAAAADjgYLjgYLjgYLjgYIDAAAABgAAAAAAAADmgZDnq5wBAAAABAAAAAAAAABGdWdhBAAAAA8AAAAAAAAA44K/44Kw44GG44GG44GGAAAAAAQAAAAAAAAASG9nZQ==',
       );
-      console.log(getFullJson());
+      console.log(getFullJson(db));
     }
 
     function alloc(capacity: number) {
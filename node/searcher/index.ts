@@ -72,8 +72,8 @@ WebAssembly.instantiate(binary.buffer, { console: { log: (ptr: number, length: n
       const input = Buffer.from(resultBase64, 'base64');
       input.writeUInt32LE(recoMem.pointer, 3 * Uint32Array.BYTES_PER_ELEMENT);
       const inputMem = writeBuffer(input);
-      console.log(new Uint32Array(memory.buffer, accumulate(inputMem.pointer, 0, 1448927000), 2));
-      console.log(new Uint32Array(memory.buffer, accumulate(inputMem.pointer, 0, 15089270000), 2));
+      console.log(new BigUint64Array(memory.buffer, accumulate(inputMem.pointer, 0, 1448927000), 2));
+      console.log(new BigUint64Array(memory.buffer, accumulate(inputMem.pointer, 0, 15089270000), 2));
       recoMem.dealloc();
       inputMem.dealloc();
     }
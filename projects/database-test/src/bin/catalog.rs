@@ -0,0 +1,334 @@
+//! Inspects and fixes catalogs without a browser or the Node harness.
+//!
+//! The steps run in the order of the usage below, whatever order the options are given in.
+
+use database_test::export::{Column, ExportFormat, ExportOptions};
+use database_test::import::csv::{parse_catalog, CsvMapping};
+use database_test::import::json::parse_games;
+use database_test::import::ndjson::NdjsonIngest;
+use database_test::{Database, GameQueryInput, GameQueryResult};
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::process;
+
+const USAGE: &str = "usage: catalog [options]
+
+  --open <snapshot>         start from a snapshot written by --save or persist()
+  --load <catalog.json>     upsert the games of a GameInput array, may be repeated
+  --load-ndjson <file>      upsert the games of a file with one GameInput per line, read in
+                            chunks so it may be larger than memory, may be repeated
+  --load-csv <catalog.csv>  upsert the games of a CSV catalog, may be repeated
+  --csv-mapping <json|@file>
+                            how --load-csv reads its columns, e.g. '{\"id\":\"appid\",\"dateOrder\":\"Dmy\"}'
+  --delete <id>             delete a game, may be repeated
+  --rename-tag <from> <to>  rename a tag, keeping its id
+  --merge-tags <from> <to>  fold the tag <from> into the tag <to>
+  --compact-tags            drop the tags that no game uses
+  --query <json|@file>      run a query, e.g. '{\"ids\":[{\"TagId\":{\"policy\":\"Include\",\"list\":[0]}}]}'
+  --explain                 print what the query did instead of its results
+  --tags                    print every tag with its id
+  --json                    print JSON instead of a table
+  --csv                     print the results of --query as CSV
+  --ndjson                  print the results of --query as one JSON object per line
+  --columns <list>          the columns of --csv, --ndjson and --json results, e.g. id,name,tags,up[0]
+  --save <snapshot>         write a snapshot that --open and open() can read";
+
+#[derive(Default)]
+struct Options {
+    open: Option<String>,
+    load: Vec<String>,
+    ndjson: Vec<String>,
+    csv: Vec<String>,
+    mapping: Option<String>,
+    delete: Vec<u32>,
+    renames: Vec<(String, String)>,
+    merges: Vec<(String, String)>,
+    compact: bool,
+    query: Option<String>,
+    explain: bool,
+    tags: bool,
+    json: bool,
+    format: Option<ExportFormat>,
+    columns: Vec<Column>,
+    save: Option<String>,
+}
+
+fn main() {
+    let result = parse_args(env::args().skip(1)).and_then(|options| run(&options));
+    if let Err(message) = result {
+        eprintln!("catalog: {}", message);
+        process::exit(1);
+    }
+}
+
+fn value<T>(args: &mut T, flag: &str) -> Result<String, String>
+where
+    T: Iterator<Item = String>,
+{
+    args.next()
+        .ok_or_else(|| format!("{} needs a value\n\n{}", flag, USAGE))
+}
+
+fn parse_args<T>(mut args: T) -> Result<Options, String>
+where
+    T: Iterator<Item = String>,
+{
+    let mut options = Options::default();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--open" => options.open = Some(value(&mut args, &flag)?),
+            "--load" => options.load.push(value(&mut args, &flag)?),
+            "--load-ndjson" => options.ndjson.push(value(&mut args, &flag)?),
+            "--load-csv" => options.csv.push(value(&mut args, &flag)?),
+            "--csv-mapping" => options.mapping = Some(value(&mut args, &flag)?),
+            "--delete" => {
+                let id = value(&mut args, &flag)?;
+                let id = id.parse().map_err(|_| format!("bad game id {}", id))?;
+                options.delete.push(id);
+            }
+            "--rename-tag" => {
+                let from = value(&mut args, &flag)?;
+                options.renames.push((from, value(&mut args, &flag)?));
+            }
+            "--merge-tags" => {
+                let from = value(&mut args, &flag)?;
+                options.merges.push((from, value(&mut args, &flag)?));
+            }
+            "--compact-tags" => options.compact = true,
+            "--query" => options.query = Some(value(&mut args, &flag)?),
+            "--explain" => options.explain = true,
+            "--tags" => options.tags = true,
+            "--json" => options.json = true,
+            "--csv" => options.format = Some(ExportFormat::Csv),
+            "--ndjson" => options.format = Some(ExportFormat::Ndjson),
+            "--columns" => {
+                options.columns = value(&mut args, &flag)?
+                    .split(',')
+                    .map(|column| column.trim().parse::<Column>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| e.to_string())?;
+            }
+            "--save" => options.save = Some(value(&mut args, &flag)?),
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            _ => return Err(format!("unknown option {}\n\n{}", flag, USAGE)),
+        }
+    }
+    Ok(options)
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let mut db = match options.open.as_ref() {
+        None => Database::new(),
+        Some(path) => {
+            let snapshot = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+            Database::restore(&snapshot).map_err(|e| format!("{}: {}", path, e))?
+        }
+    };
+    for path in options.load.iter() {
+        let json = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let batch = parse_games(&json).map_err(|e| format!("{}: {}", path, e))?;
+        for error in batch.errors.iter() {
+            let field = if error.path.is_empty() {
+                "record"
+            } else {
+                &error.path
+            };
+            eprintln!("{}[{}]: {}: {}", path, error.index, field, error.message);
+        }
+        db.upsert_games(batch.games)
+            .map_err(|e| format!("{}: {}", path, e))?;
+    }
+    for path in options.ndjson.iter() {
+        load_ndjson(&mut db, path).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    if !options.csv.is_empty() {
+        let mapping = match options.mapping.as_ref() {
+            None => CsvMapping::default(),
+            Some(mapping) => serde_json::from_str(&read_argument(mapping)?)
+                .map_err(|e| format!("bad CSV mapping: {}", e))?,
+        };
+        for path in options.csv.iter() {
+            let csv = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            let import = parse_catalog(&csv, &mapping).map_err(|e| format!("{}: {}", path, e))?;
+            for error in import.errors.iter() {
+                let column = error.column.as_deref().unwrap_or("row");
+                eprintln!("{}:{}: {}: {}", path, error.line, column, error.message);
+            }
+            db.upsert_games(import.games)
+                .map_err(|e| format!("{}: {}", path, e))?;
+        }
+    }
+
+    for id in db.delete_games(&options.delete) {
+        eprintln!("deleted game {}", id);
+    }
+    for (from, to) in options.renames.iter() {
+        db.rename_tag(from, to.clone())
+            .ok_or_else(|| format!("cannot rename tag {} to {}", from, to))?;
+    }
+    for (from, to) in options.merges.iter() {
+        db.merge_tags(from, to)
+            .ok_or_else(|| format!("cannot merge tag {} into {}", from, to))?;
+    }
+    if options.compact {
+        db.compact_tags();
+    }
+
+    if options.tags {
+        let tags = db.tags();
+        if options.json {
+            println!("{}", to_json(&tags)?);
+        } else {
+            let rows = tags
+                .iter()
+                .map(|(id, tag)| vec![id.to_string(), tag.clone()])
+                .collect();
+            print_table(vec!["id".to_string(), "tag".to_string()], rows);
+        }
+    }
+    if let Some(query) = options.query.as_ref() {
+        let query = serde_json::from_str::<GameQueryInput>(&read_argument(query)?)
+            .map_err(|e| format!("bad query: {}", e))?
+            .into_query()
+            .map_err(|e| e.to_string())?;
+        if options.explain {
+            let start = std::time::Instant::now();
+            let explain = db
+                .explain(&query, || start.elapsed().as_secs_f64() * 1000.0)
+                .map_err(|e| e.to_string())?;
+            println!("{}", to_json(&explain)?);
+        } else if options.format.is_some() || !options.columns.is_empty() {
+            let export = ExportOptions {
+                format: options.format.unwrap_or(ExportFormat::Json),
+                columns: options.columns.clone(),
+                ..ExportOptions::default()
+            };
+            let out = db.export(&query, &export).map_err(|e| e.to_string())?;
+            print!("{}", out);
+        } else {
+            let results = db.filter(&query).map_err(|e| e.to_string())?;
+            if options.json {
+                println!("{}", to_json(&results)?);
+            } else {
+                print_results(&results, &db.tags());
+            }
+        }
+    }
+
+    if let Some(path) = options.save.as_ref() {
+        let snapshot = db.snapshot().map_err(|e| e.to_string())?;
+        fs::write(path, snapshot).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    Ok(())
+}
+
+fn load_ndjson(db: &mut Database, path: &str) -> Result<(), String> {
+    let mut file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut ingest = NdjsonIngest::new();
+    let mut chunk = vec![0; 1 << 20];
+    loop {
+        let read = file.read(&mut chunk).map_err(|e| e.to_string())?;
+        if read == 0 {
+            break;
+        }
+        db.upsert_games(ingest.feed(&chunk[..read]))
+            .map_err(|e| e.to_string())?;
+    }
+    db.upsert_games(ingest.finish())
+        .map_err(|e| e.to_string())?;
+    let progress = ingest.progress();
+    for error in progress.errors.iter() {
+        eprintln!("{}:{}: {}", path, error.line, error.message);
+    }
+    if progress.failed > progress.errors.len() as u64 {
+        let more = progress.failed - progress.errors.len() as u64;
+        eprintln!("{}: {} more lines failed", path, more);
+    }
+    eprintln!(
+        "{}: loaded {} games from {} lines",
+        path, progress.games, progress.lines
+    );
+    Ok(())
+}
+
+/// An argument given inline, or as `@file` to read it from a file.
+fn read_argument(argument: &str) -> Result<String, String> {
+    match argument.strip_prefix('@') {
+        Some(path) => fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e)),
+        None => Ok(argument.to_string()),
+    }
+}
+
+fn to_json<T>(value: &T) -> Result<String, String>
+where
+    T: serde::Serialize,
+{
+    serde_json::to_string_pretty(value).map_err(|e| e.to_string())
+}
+
+fn print_results(results: &[GameQueryResult], tags: &BTreeMap<u32, String>) {
+    let windows: BTreeSet<u32> = results
+        .iter()
+        .filter_map(|result| result.recommendations.as_ref())
+        .flat_map(|scores| scores.keys().cloned())
+        .collect();
+    let mut header = vec![
+        "id".to_string(),
+        "name".to_string(),
+        "release".to_string(),
+        "tags".to_string(),
+    ];
+    for window in windows.iter() {
+        header.push(format!("up[{}]", window));
+        header.push(format!("down[{}]", window));
+    }
+    let rows = results
+        .iter()
+        .map(|result| {
+            let names: Vec<&str> = result
+                .tags
+                .iter()
+                .flatten()
+                .map(|id| tags.get(id).map(|tag| tag.as_str()).unwrap_or("?"))
+                .collect();
+            let mut row = vec![
+                result.id.to_string(),
+                result.name.clone(),
+                result
+                    .releaseDate
+                    .map(|date| date.to_string())
+                    .unwrap_or_default(),
+                names.join(","),
+            ];
+            for window in windows.iter() {
+                let score = result
+                    .recommendations
+                    .as_ref()
+                    .and_then(|scores| scores.get(window));
+                row.push(score.map(|s| s.up.to_string()).unwrap_or_default());
+                row.push(score.map(|s| s.down.to_string()).unwrap_or_default());
+            }
+            row
+        })
+        .collect();
+    print_table(header, rows);
+}
+
+fn print_table(header: Vec<String>, rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = header.iter().map(|cell| cell.chars().count()).collect();
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
@@ -0,0 +1,364 @@
+//! Serves one database over HTTP on localhost, for tools that do not embed the wasm module.
+//! Requests are handled one at a time, so every request sees the effects of the ones
+//! before it.
+//!
+//!     POST /games          upsert a GameInput array, like updateGames(), responds with the
+//!                          rejected records
+//!     POST /query          run a GameQueryInput, like filterGames()
+//!     POST /explain        explain a GameQueryInput
+//!     POST /export         {"query": GameQueryInput, "options": ExportOptions}, as CSV,
+//!                          NDJSON or JSON
+//!     GET  /tags           every tag name by id
+//!     POST /tags/rename    {"from": .., "to": ..}
+//!     POST /tags/merge     {"from": .., "to": ..}
+//!     POST /tags/compact
+//!     GET  /metrics        counters and latencies since the start or the last reset
+//!     POST /metrics/reset  responds with the metrics, then resets them
+//!     GET  /snapshot       the database as persist() writes it
+//!     PUT  /snapshot       replace the database with a snapshot
+//!
+//! Only requests meant for this server are served, so that web pages open in a browser
+//! on the same machine cannot use it: the `Host` has to be `127.0.0.1:<port>` or
+//! `localhost:<port>`, requests from a page, which carry an `Origin`, are refused, and
+//! POST bodies have to be sent as `application/json`, which a page cannot do without
+//! asking first.
+#![allow(non_snake_case)]
+
+use database_test::export::ExportOptions;
+use database_test::import::json::parse_games;
+use database_test::{Database, DatabaseError, DatabaseResult, GameQueryInput};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage: server [--port <port>] [--open <snapshot>]";
+/// Larger bodies are refused before anything is read into memory.
+const MAX_BODY: usize = 64 << 20;
+/// The request line and headers together.
+const MAX_HEAD: u64 = 64 << 10;
+/// Requests are handled one at a time, so a client that stalls must not hold up the rest:
+/// reading a request and writing its response may take this long each, however slowly
+/// the client sends or receives.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    contentType: &'static str,
+    body: Vec<u8>,
+}
+impl Response {
+    fn json<T>(value: &T) -> Response
+    where
+        T: Serialize,
+    {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response {
+                status: 200,
+                contentType: "application/json",
+                body,
+            },
+            Err(e) => Response::error(&DatabaseError::serialize(e)),
+        }
+    }
+
+    fn error(error: &DatabaseError) -> Response {
+        let body = serde_json::to_vec(error).unwrap_or_default();
+        Response {
+            status: 400,
+            contentType: "application/json",
+            body,
+        }
+    }
+
+    fn too_large() -> Response {
+        Response {
+            status: 413,
+            contentType: "text/plain",
+            body: format!("bodies are limited to {} bytes", MAX_BODY).into_bytes(),
+        }
+    }
+
+    fn text(status: u16, message: String) -> Response {
+        Response {
+            status,
+            contentType: "text/plain",
+            body: message.into_bytes(),
+        }
+    }
+
+    fn not_found() -> Response {
+        Response {
+            status: 404,
+            contentType: "text/plain",
+            body: b"not found".to_vec(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ExportInput {
+    query: GameQueryInput,
+    #[serde(default)]
+    options: ExportOptions,
+}
+
+#[derive(Deserialize)]
+struct TagEditInput {
+    from: String,
+    to: String,
+}
+
+fn main() {
+    let mut port = 8080u16;
+    let mut db = Database::new();
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().unwrap_or_else(|| exit(USAGE));
+        match flag.as_str() {
+            "--port" => port = value.parse().unwrap_or_else(|_| exit(USAGE)),
+            "--open" => {
+                let snapshot = std::fs::read(&value).unwrap_or_else(|e| exit(&e.to_string()));
+                db = Database::restore(&snapshot).unwrap_or_else(|e| exit(&e.to_string()));
+            }
+            _ => exit(USAGE),
+        }
+    }
+
+    db.set_clock(clock);
+
+    let listener = TcpListener::bind(("127.0.0.1", port)).unwrap_or_else(|e| exit(&e.to_string()));
+    eprintln!("listening on http://127.0.0.1:{}", port);
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| {
+            let response = match read_request(&mut Deadline::new(&stream), port) {
+                Ok(request) => route(&mut db, request),
+                Err(response) => response,
+            };
+            write_response(&mut Deadline::new(&stream), response)
+        });
+        if let Err(e) = result {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+/// Milliseconds since the first call.
+fn clock() -> f64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs_f64() * 1000.0
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("server: {}", message);
+    process::exit(1);
+}
+
+fn route(db: &mut Database, request: Request) -> Response {
+    let result = match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/games") => std::str::from_utf8(&request.body)
+            .map_err(DatabaseError::parse)
+            .and_then(parse_games)
+            .and_then(|batch| {
+                db.upsert_games(batch.games)?;
+                Ok(Response::json(&batch.errors))
+            }),
+        ("POST", "/query") => parse::<GameQueryInput>(&request.body)
+            .and_then(|input| input.into_query())
+            .and_then(|query| db.filter(&query))
+            .map(|results| Response::json(&results)),
+        ("POST", "/explain") => parse::<GameQueryInput>(&request.body)
+            .and_then(|input| input.into_query())
+            .and_then(|query| db.explain(&query, clock))
+            .map(|explain| Response::json(&explain)),
+        ("POST", "/export") => parse::<ExportInput>(&request.body).and_then(|input| {
+            let query = input.query.into_query()?;
+            let body = db.export(&query, &input.options)?;
+            Ok(Response {
+                status: 200,
+                contentType: input.options.format.content_type(),
+                body: body.into_bytes(),
+            })
+        }),
+        ("GET", "/tags") => Ok(Response::json(&db.tags())),
+        ("POST", "/tags/rename") => parse::<TagEditInput>(&request.body)
+            .map(|input| Response::json(&db.rename_tag(&input.from, input.to))),
+        ("POST", "/tags/merge") => parse::<TagEditInput>(&request.body)
+            .map(|input| Response::json(&db.merge_tags(&input.from, &input.to))),
+        ("POST", "/tags/compact") => Ok(Response::json(&db.compact_tags())),
+        ("GET", "/metrics") => Ok(Response::json(&db.metrics())),
+        ("POST", "/metrics/reset") => {
+            let response = Response::json(&db.metrics());
+            db.reset_metrics();
+            Ok(response)
+        }
+        ("GET", "/snapshot") => db.snapshot().map(|body| Response {
+            status: 200,
+            contentType: "application/octet-stream",
+            body,
+        }),
+        ("PUT", "/snapshot") => Database::restore(&request.body).map(|mut restored| {
+            restored.set_clock(clock);
+            *db = restored;
+            Response::json(&())
+        }),
+        _ => Ok(Response::not_found()),
+    };
+    result.unwrap_or_else(|e| Response::error(&e))
+}
+
+fn parse<'a, T>(body: &'a [u8]) -> DatabaseResult<T>
+where
+    T: Deserialize<'a>,
+{
+    serde_json::from_slice(body).map_err(DatabaseError::parse)
+}
+
+/// A stream that fails once `TIMEOUT` has passed since it was made, rather than only when
+/// a single read or write takes that long.
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    until: Instant,
+}
+impl<'a> Deadline<'a> {
+    fn new(stream: &'a TcpStream) -> Self {
+        Deadline {
+            stream,
+            until: Instant::now() + TIMEOUT,
+        }
+    }
+
+    fn remaining(&self) -> io::Result<Duration> {
+        match self.until.checked_duration_since(Instant::now()) {
+            Some(left) if !left.is_zero() => Ok(left),
+            _ => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the client took too long",
+            )),
+        }
+    }
+}
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.set_read_timeout(Some(self.remaining()?))?;
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+impl Write for Deadline<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.set_write_timeout(Some(self.remaining()?))?;
+        let mut stream = self.stream;
+        stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut stream = self.stream;
+        stream.flush()
+    }
+}
+
+fn read_request<R>(stream: &mut R, port: u16) -> Result<Request, Response>
+where
+    R: Read,
+{
+    let bad = |message: String| Response::error(&DatabaseError::invalid_input(message));
+    let mut reader = BufReader::new(stream);
+    let mut head = reader.by_ref().take(MAX_HEAD);
+    let mut line = String::new();
+    head.read_line(&mut line).map_err(|e| bad(e.to_string()))?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(bad(format!("bad request line {:?}", line))),
+    };
+    let mut length = 0;
+    let (mut host, mut origin, mut contentType) = (None, None, None);
+    loop {
+        line.clear();
+        head.read_line(&mut line).map_err(|e| bad(e.to_string()))?;
+        if !line.ends_with('\n') {
+            return Err(bad("the request head is incomplete or too long".to_string()));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            match name.to_ascii_lowercase().as_str() {
+                "content-length" => {
+                    length = value
+                        .parse()
+                        .map_err(|_| bad(format!("bad content length {:?}", value)))?;
+                }
+                "host" => host = Some(value.to_string()),
+                "origin" => origin = Some(value.to_string()),
+                "content-type" => contentType = Some(value.to_string()),
+                _ => (),
+            }
+        }
+    }
+    let local = [format!("127.0.0.1:{}", port), format!("localhost:{}", port)];
+    if !host
+        .as_ref()
+        .is_some_and(|host| local.iter().any(|local| host.eq_ignore_ascii_case(local)))
+    {
+        let host = host.as_deref().unwrap_or("no host");
+        let message = format!("requests for {} are not served", host);
+        return Err(Response::text(403, message));
+    }
+    if let Some(origin) = origin {
+        let message = format!("requests from {} are not served", origin);
+        return Err(Response::text(403, message));
+    }
+    let json = contentType.as_ref().is_some_and(|contentType| {
+        let mediaType = contentType.split(';').next().unwrap_or_default();
+        mediaType.trim().eq_ignore_ascii_case("application/json")
+    });
+    if method == "POST" && !json {
+        let contentType = contentType.as_deref().unwrap_or("no content type");
+        let message = format!("POST bodies must be application/json, not {}", contentType);
+        return Err(Response::text(415, message));
+    }
+    if length > MAX_BODY {
+        return Err(Response::too_large());
+    }
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|e| bad(e.to_string()))?;
+    Ok(Request { method, path, body })
+}
+
+fn write_response<W>(stream: &mut W, response: Response) -> io::Result<()>
+where
+    W: Write,
+{
+    let reason = match response.status {
+        200 => "OK",
+        403 => "Forbidden",
+        404 => "Not Found",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        _ => "Bad Request",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason,
+        response.contentType,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)
+}
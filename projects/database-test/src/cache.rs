@@ -0,0 +1,109 @@
+use crate::query::query::game::{GameQuery, GameQueryResult};
+use std::collections::{HashMap, HashSet};
+
+/// Results of recent `Database::filter` calls by `GameQuery::key`, least recently used
+/// first out. Disabled until it is given a capacity.
+#[derive(Clone)]
+pub struct ResultCache {
+    capacity: usize,
+    entries: HashMap<String, Entry>,
+    clock: u64,
+}
+
+#[derive(Clone)]
+struct Entry {
+    query: GameQuery,
+    results: Vec<GameQueryResult>,
+    /// Ids of `results`, to tell quickly whether a changed game was part of them.
+    ids: HashSet<u32>,
+    lastUsed: u64,
+}
+
+impl Default for ResultCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl ResultCache {
+    pub fn new() -> Self {
+        ResultCache {
+            capacity: 0,
+            entries: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Keeps at most `capacity` results; 0 disables the cache and drops everything in it.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.evict();
+        }
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn get(&mut self, key: &str) -> Option<&Vec<GameQueryResult>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|entry| {
+            entry.lastUsed = clock;
+            &entry.results
+        })
+    }
+
+    pub fn insert(&mut self, key: String, query: GameQuery, results: Vec<GameQueryResult>) {
+        if !self.is_enabled() {
+            return;
+        }
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            self.evict();
+        }
+        self.clock += 1;
+        let ids = results.iter().map(|result| result.id).collect();
+        self.entries.insert(
+            key,
+            Entry {
+                query,
+                results,
+                ids,
+                lastUsed: self.clock,
+            },
+        );
+    }
+
+    /// Drops every result that the game `id` was part of, and every result for which
+    /// `matches` says the game now belongs in it.
+    pub fn invalidate_game<T>(&mut self, id: u32, mut matches: T)
+    where
+        T: FnMut(&GameQuery) -> bool,
+    {
+        self.entries
+            .retain(|_, entry| !entry.ids.contains(&id) && !matches(&entry.query));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.lastUsed)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+        }
+    }
+}
@@ -0,0 +1,464 @@
+use crate::cache::ResultCache;
+use crate::entity::entity::game::{Game, GameInput};
+use crate::entity::entity::recommendation::Recommendation;
+use crate::entity::entity::tag::{LegacyTagRegistry, TagRegistry};
+use crate::error::{DatabaseError, DatabaseResult, ErrorCode};
+use crate::export::{write_results, ExportOptions};
+use crate::metrics::{Metrics, MetricsReport, QueryKind};
+use crate::query::query::explain::{QueryExplain, StageCounts};
+use crate::query::query::game::{GameQuery, GameQueryResult};
+
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+
+/// The whole catalog. The wasm exports are a thin layer over this; native code can use it
+/// directly and gets the same filtering semantics as the browser.
+pub struct Database {
+    root: Root,
+    /// The state as of `begin`, restored by `rollback`.
+    savepoint: Option<Box<Root>>,
+    cache: RefCell<ResultCache>,
+    metrics: RefCell<Metrics>,
+    /// Times queries for the metrics, in milliseconds. Queries are not timed without it.
+    clock: Option<fn() -> f64>,
+}
+
+/// Starts every snapshot, followed by `SNAPSHOT_VERSION` as a `u32`. Snapshots without it
+/// start with the `u64` user count, which these 8 bytes could only be with billions of
+/// users, so the two cannot be mistaken for each other.
+const SNAPSHOT_MAGIC: &[u8] = b"GmDb";
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// What a snapshot holds.
+#[derive(Serialize, Deserialize, Clone)]
+struct Root {
+    users: HashMap<u32, User>,
+    games: HashMap<u32, Game>,
+    allTags: TagRegistry,
+}
+
+/// The layout of snapshots from before the tag id counter and the version header. They
+/// end with a query counter after `allTags`; bincode ignores such trailing bytes.
+#[derive(Deserialize)]
+struct LegacyRoot {
+    users: HashMap<u32, User>,
+    games: HashMap<u32, Game>,
+    allTags: LegacyTagRegistry,
+}
+impl From<LegacyRoot> for Root {
+    fn from(legacy: LegacyRoot) -> Self {
+        Root {
+            users: legacy.users,
+            games: legacy.games,
+            allTags: legacy.allTags.0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct User {
+    pub id: u32,
+    pub name: String,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Database {
+    pub fn new() -> Self {
+        Database {
+            root: Root {
+                users: HashMap::new(),
+                games: HashMap::new(),
+                allTags: TagRegistry::new(),
+            },
+            savepoint: None,
+            cache: RefCell::new(ResultCache::new()),
+            metrics: RefCell::new(Metrics::new()),
+            clock: None,
+        }
+    }
+
+    /// Loads a database from bytes produced by `snapshot`.
+    /// Also reads `LegacyRoot` snapshots, which have no version header.
+    pub fn restore(snapshot: &[u8]) -> DatabaseResult<Self> {
+        let root = match snapshot.strip_prefix(SNAPSHOT_MAGIC) {
+            Some(versioned) => {
+                let (version, body) = versioned.split_at(versioned.len().min(4));
+                match <[u8; 4]>::try_from(version).map(u32::from_le_bytes) {
+                    Ok(SNAPSHOT_VERSION) => bincode::deserialize(body),
+                    _ => {
+                        return Err(DatabaseError::parse(format!(
+                            "unsupported snapshot version {:?}",
+                            version
+                        )))
+                    }
+                }
+            }
+            None => bincode::deserialize::<LegacyRoot>(snapshot).map(Root::from),
+        }
+        .map_err(DatabaseError::parse)?;
+        Ok(Database {
+            root,
+            savepoint: None,
+            cache: RefCell::new(ResultCache::new()),
+            metrics: RefCell::new(Metrics::new()),
+            clock: None,
+        })
+    }
+
+    /// Serializes the current state, including changes of a transaction that has not been
+    /// committed yet.
+    pub fn snapshot(&self) -> DatabaseResult<Vec<u8>> {
+        let mut snapshot = SNAPSHOT_MAGIC.to_vec();
+        snapshot.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut snapshot, &self.root).map_err(DatabaseError::serialize)?;
+        Ok(snapshot)
+    }
+
+    pub fn to_json(&self) -> DatabaseResult<String> {
+        serde_json::to_string(&self.root).map_err(DatabaseError::serialize)
+    }
+
+    /// Fails only when the tag ids run out; the games before the failing one stay upserted.
+    pub fn upsert_games<T>(&mut self, games: T) -> DatabaseResult<()>
+    where
+        T: IntoIterator<Item = GameInput>,
+    {
+        let root = &mut self.root;
+        let cache = self.cache.get_mut();
+        let ingest = self.metrics.get_mut().ingest();
+        ingest.batches += 1;
+        for game in games {
+            let game = Game::from_game_input(game, &mut root.allTags)?;
+            ingest.games += 1;
+            Self::invalidate(cache, &game);
+            root.games.insert(game.id, game);
+        }
+        Ok(())
+    }
+
+    /// Replaces the recommendations of an existing game, e.g. with those of
+    /// `import::steam::parse_histogram`.
+    pub fn set_recommendations(
+        &mut self,
+        id: u32,
+        recommendations: Vec<Recommendation>,
+    ) -> DatabaseResult<()> {
+        let game = self
+            .root
+            .games
+            .get_mut(&id)
+            .ok_or_else(|| DatabaseError::invalid_input(format!("there is no game {}", id)))?;
+        game.recommendations = Some(recommendations);
+        self.metrics.get_mut().ingest().recommendations += 1;
+        Self::invalidate(self.cache.get_mut(), game);
+        Ok(())
+    }
+
+    /// Removes the given games and returns the ids of those that existed. Their tags stay
+    /// registered until `compact_tags`.
+    pub fn delete_games<'a, T>(&mut self, ids: T) -> Vec<u32>
+    where
+        T: IntoIterator<Item = &'a u32>,
+    {
+        let games = &mut self.root.games;
+        let cache = self.cache.get_mut();
+        let deleted: Vec<u32> = ids
+            .into_iter()
+            .filter_map(|id| games.remove(id).map(|game| game.id))
+            .inspect(|id| cache.invalidate_game(*id, |_| false))
+            .collect();
+        self.metrics.get_mut().ingest().deleted += deleted.len() as u64;
+        deleted
+    }
+
+    pub fn upsert_users<T>(&mut self, users: T)
+    where
+        T: IntoIterator<Item = User>,
+    {
+        let root = &mut self.root;
+        for user in users {
+            root.users.insert(user.id, user);
+        }
+    }
+
+    /// An independent copy of the current state, including changes of a transaction that
+    /// has not been committed yet. The copy is not in a transaction itself, so it cannot
+    /// roll back to this database's savepoint, and starts with an empty cache of the same
+    /// capacity and fresh metrics.
+    pub fn fork(&self) -> Self {
+        let mut cache = ResultCache::new();
+        cache.set_capacity(self.cache.borrow().capacity());
+        Database {
+            root: self.root.clone(),
+            savepoint: None,
+            cache: RefCell::new(cache),
+            metrics: RefCell::new(Metrics::new()),
+            clock: self.clock,
+        }
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.savepoint.is_some()
+    }
+
+    /// Starts a transaction. Everything up to `commit` can be undone with `rollback`,
+    /// including tag ids minted on the way. Transactions do not nest.
+    pub fn begin(&mut self) -> DatabaseResult<()> {
+        if self.in_transaction() {
+            return Err(DatabaseError::transaction("a transaction is already open"));
+        }
+        self.savepoint = Some(Box::new(self.root.clone()));
+        Ok(())
+    }
+
+    pub fn commit(&mut self) -> DatabaseResult<()> {
+        match self.savepoint.take() {
+            Some(_) => Ok(()),
+            None => Err(DatabaseError::transaction("no transaction is open")),
+        }
+    }
+
+    /// Puts the database back exactly as it was at `begin`.
+    pub fn rollback(&mut self) -> DatabaseResult<()> {
+        match self.savepoint.take() {
+            Some(root) => {
+                self.root = *root;
+                self.cache.get_mut().clear();
+                Ok(())
+            }
+            None => Err(DatabaseError::transaction("no transaction is open")),
+        }
+    }
+
+    /// Runs `task` in a transaction that is committed if it succeeds and rolled back if
+    /// it fails.
+    pub fn transaction<T, R>(&mut self, task: T) -> DatabaseResult<R>
+    where
+        T: FnOnce(&mut Database) -> DatabaseResult<R>,
+    {
+        self.begin()?;
+        match task(self) {
+            Ok(value) => {
+                self.commit()?;
+                Ok(value)
+            }
+            Err(error) => {
+                self.rollback()?;
+                Err(error)
+            }
+        }
+    }
+
+    /// Drops the cached results that `game`, as it is now, could change: those it is in
+    /// and those it would now match.
+    fn invalidate(cache: &mut ResultCache, game: &Game) {
+        if cache.is_enabled() {
+            cache.invalidate_game(game.id, |query| {
+                !matches!(evaluate(query, game, &mut None), Ok(None))
+            });
+        }
+    }
+
+    /// Keeps the results of up to `capacity` recent queries, see `filter`. 0, the default,
+    /// turns the cache off.
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.cache.get_mut().set_capacity(capacity);
+    }
+
+    /// With the cache on, repeating a query returns the cached results until a change to
+    /// a game could alter them: an upsert of a game that is in them or that would now
+    /// match, or a delete of a game that is in them.
+    pub fn filter(&self, query: &GameQuery) -> DatabaseResult<Vec<GameQueryResult>> {
+        self.timed(QueryKind::Filter, || self.run_filter(query))
+    }
+
+    fn run_filter(&self, query: &GameQuery) -> DatabaseResult<Vec<GameQueryResult>> {
+        let key = match self.cache.try_borrow_mut() {
+            Ok(mut cache) if cache.is_enabled() => {
+                let key = query.key()?;
+                if let Some(results) = cache.get(&key) {
+                    if let Ok(mut metrics) = self.metrics.try_borrow_mut() {
+                        metrics.cache_hit();
+                    }
+                    return Ok(results.clone());
+                }
+                Some(key)
+            }
+            _ => None,
+        };
+        let results = self.scan(query, &mut None)?;
+        if let (Some(key), Ok(mut cache)) = (key, self.cache.try_borrow_mut()) {
+            cache.insert(key, query.clone(), results.clone());
+        }
+        Ok(results)
+    }
+
+    /// Runs `query` without the cache and reports what each stage of it did. `clock` is
+    /// read before and after, in milliseconds.
+    pub fn explain<C>(&self, query: &GameQuery, mut clock: C) -> DatabaseResult<QueryExplain>
+    where
+        C: FnMut() -> f64,
+    {
+        let cached = match self.cache.try_borrow() {
+            Ok(cache) if cache.is_enabled() => cache.contains(&query.key()?),
+            _ => false,
+        };
+        let mut counts = StageCounts::new();
+        let start = clock();
+        let results = self.scan(query, &mut Some(&mut counts))?;
+        let elapsedMs = clock() - start;
+        if let Ok(mut metrics) = self.metrics.try_borrow_mut() {
+            metrics.query(QueryKind::Explain, Some(elapsedMs));
+        }
+        Ok(QueryExplain {
+            scanned: self.root.games.len() as u64,
+            matched: results.len() as u64,
+            stages: counts.into_stats(),
+            indexes: Vec::new(),
+            cached,
+            elapsedMs,
+        })
+    }
+
+    fn scan(
+        &self,
+        query: &GameQuery,
+        counts: &mut Option<&mut StageCounts>,
+    ) -> DatabaseResult<Vec<GameQueryResult>> {
+        let mut results = Vec::<GameQueryResult>::new();
+        for game in self.root.games.values() {
+            if let Some(result) = evaluate(query, game, counts)? {
+                results.push(result);
+            }
+        }
+        query.sortQuery.run(&mut results);
+        Ok(results)
+    }
+
+    /// Runs `query` and writes its results as `options` asks, see `export::write_results`.
+    pub fn export(&self, query: &GameQuery, options: &ExportOptions) -> DatabaseResult<String> {
+        self.timed(QueryKind::Export, || {
+            write_results(
+                &self.run_filter(query)?,
+                &self.root.allTags,
+                query.recommendationQuery.names(),
+                options,
+            )
+        })
+    }
+
+    /// Runs `task` and counts it as a query of `kind`, with its latency if there is a
+    /// clock. Metrics are only bookkeeping, so they are skipped rather than fail a query
+    /// when they are in use.
+    fn timed<T, R>(&self, kind: QueryKind, task: T) -> DatabaseResult<R>
+    where
+        T: FnOnce() -> DatabaseResult<R>,
+    {
+        let start = self.clock.map(|clock| clock());
+        let result = task();
+        let elapsedMs = self.clock.zip(start).map(|(clock, start)| clock() - start);
+        if let Ok(mut metrics) = self.metrics.try_borrow_mut() {
+            metrics.query(kind, elapsedMs);
+        }
+        result
+    }
+
+    /// Gives the database a clock in milliseconds, e.g. `performance.now`, to time
+    /// queries with for the metrics.
+    pub fn set_clock(&mut self, clock: fn() -> f64) {
+        self.clock = Some(clock);
+    }
+
+    pub fn metrics(&self) -> MetricsReport {
+        let tags = self.root.allTags.iter().count() as u64;
+        let games = self.root.games.len() as u64;
+        self.metrics.borrow().report(games, tags)
+    }
+
+    pub fn reset_metrics(&mut self) {
+        *self.metrics.get_mut() = Metrics::new();
+    }
+
+    /// Every tag name by id.
+    pub fn tags(&self) -> BTreeMap<u32, String> {
+        self.root
+            .allTags
+            .iter()
+            .map(|(tag, id)| (id, tag.to_string()))
+            .collect()
+    }
+
+    /// See `TagRegistry::compact`. Only unused tags go, so no result changes.
+    pub fn compact_tags(&mut self) -> Vec<u32> {
+        let root = &mut self.root;
+        root.allTags.compact(root.games.values())
+    }
+
+    /// See `TagRegistry::rename`.
+    pub fn rename_tag(&mut self, from: &str, to: String) -> Option<u32> {
+        self.root.allTags.rename(from, to)
+    }
+
+    /// See `TagRegistry::merge`.
+    pub fn merge_tags(&mut self, from: &str, into: &str) -> Option<(u32, u32)> {
+        let root = &mut self.root;
+        let fromId = root.allTags.get_id(from)?;
+        let changed: Vec<u32> = root
+            .games
+            .values()
+            .filter(|game| game.tags.iter().flatten().any(|id| *id == fromId))
+            .map(|game| game.id)
+            .collect();
+        let merged = root.allTags.merge(from, into, root.games.values_mut())?;
+        let cache = self.cache.get_mut();
+        for id in changed {
+            Self::invalidate(cache, &root.games[&id]);
+        }
+        Some(merged)
+    }
+}
+
+/// Runs `query` against a single game, without sorting.
+fn evaluate(
+    query: &GameQuery,
+    game: &Game,
+    counts: &mut Option<&mut StageCounts>,
+) -> DatabaseResult<Option<GameQueryResult>> {
+    if !query.idQuery.run_counted(game, counts) {
+        return Ok(None);
+    }
+    let mut score = None;
+    // a game without reviews has none in any range, rather than being exempt from them
+    let recommendations = match game.recommendations.as_deref() {
+        None if query.recommendationQuery.has_predicates() => Some(&[][..]),
+        recommendations => recommendations,
+    };
+    if let Some(recommendations) = recommendations {
+        match query
+            .recommendationQuery
+            .run_counted(recommendations, game.releaseDate, counts)
+        {
+            Ok(Some(s)) => score = Some(s),
+            Ok(None) => return Ok(None),
+            Err(_) => {
+                return Err(DatabaseError::new(
+                    ErrorCode::Overflow,
+                    format!("recommendations of game {} overflowed", game.id),
+                ))
+            }
+        }
+    }
+    Ok(Some(GameQueryResult {
+        id: game.id,
+        name: game.name.clone(),
+        tags: game.tags.as_ref().cloned(),
+        releaseDate: game.releaseDate,
+        recommendations: score,
+    }))
+}
@@ -2,8 +2,9 @@ pub mod entity {
     pub mod game {
         use super::recommendation::Recommendation;
         use super::tag::TagRegistry;
+        use crate::error::DatabaseResult;
         use serde::{Deserialize, Serialize};
-        use std::iter::FromIterator;
+        use std::collections::HashSet;
 
         #[derive(Serialize, Deserialize)]
         pub struct GameInput {
@@ -14,75 +15,200 @@ pub mod entity {
             pub recommendations: Option<Vec<Recommendation>>,
         }
 
-        #[derive(Serialize, Deserialize)]
+        #[derive(Serialize, Deserialize, Clone)]
         pub struct Game {
             pub id: u32,
             pub name: String,
             pub tags: Option<Vec<u32>>,
+            /// Unix time in seconds, same as `Recommendation.date`.
             pub releaseDate: Option<u32>,
             pub recommendations: Option<Vec<Recommendation>>,
         }
         impl Game {
-            pub fn from_game_input(json: GameInput, allTags: &mut TagRegistry) -> Self {
+            pub fn from_game_input(
+                json: GameInput,
+                allTags: &mut TagRegistry,
+            ) -> DatabaseResult<Self> {
                 let tagsIds = match json.tags {
                     None => None,
-                    Some(tags) => {
-                        let iter = tags.into_iter().map(|tag| allTags.get_id_by_tag(tag));
-                        Some(Vec::from_iter(iter))
-                    }
+                    Some(tags) => Some(
+                        tags.into_iter()
+                            .map(|tag| allTags.get_id_by_tag(tag))
+                            .collect::<DatabaseResult<Vec<u32>>>()?,
+                    ),
                 };
-                Game {
+                Ok(Game {
                     id: json.id,
                     name: json.name,
                     tags: tagsIds,
                     releaseDate: json.releaseDate,
                     recommendations: json.recommendations,
+                })
+            }
+
+            /// Points every occurrence of `from` at `into`, keeping the first occurrence of
+            /// each id so that the tag order stays as it was.
+            pub fn replace_tag(&mut self, from: u32, into: u32) {
+                if let Some(tags) = self.tags.as_mut() {
+                    if !tags.contains(&from) {
+                        return;
+                    }
+                    let mut seen = HashSet::<u32>::with_capacity(tags.len());
+                    tags.retain(|id| {
+                        let id = if *id == from { into } else { *id };
+                        seen.insert(id)
+                    });
+                    tags.iter_mut()
+                        .filter(|id| **id == from)
+                        .for_each(|id| *id = into);
                 }
             }
         }
     }
 
     pub mod tag {
-        use serde::de::{MapAccess, Visitor};
-        use serde::ser::SerializeMap;
+        use super::game::Game;
+        use crate::error::{DatabaseError, DatabaseResult};
+        use serde::de::{self, MapAccess, Visitor};
+        use serde::ser::{SerializeMap, SerializeStruct};
         use serde::{Deserialize, Deserializer, Serialize, Serializer};
-        use std::collections::HashMap;
+        use std::collections::{HashMap, HashSet};
         use std::fmt;
 
+        #[derive(Clone)]
         pub struct TagRegistry {
             map: HashMap<String, u32>,
+            // ids are never reused, even after the tag they pointed to is gone
+            nextId: u32,
+        }
+        impl Default for TagRegistry {
+            fn default() -> Self {
+                Self::new()
+            }
         }
         impl TagRegistry {
             pub fn new() -> Self {
                 TagRegistry {
                     map: HashMap::<String, u32>::new(),
+                    nextId: 0,
                 }
             }
 
-            pub fn get_id_by_tag(&mut self, tag: String) -> u32 {
+            /// Fails on ids that leave no room for `nextId`; the caller reports it as a
+            /// parse error of the snapshot.
+            fn restore(map: HashMap<String, u32>, nextId: u32) -> Result<Self, String> {
+                // never hand out an id that is already taken, whatever the snapshot says
+                let mut next = nextId;
+                for id in map.values() {
+                    let after = id
+                        .checked_add(1)
+                        .ok_or_else(|| format!("tag id {} is out of range", id))?;
+                    next = next.max(after);
+                }
+                Ok(TagRegistry { map, nextId: next })
+            }
+
+            /// Fails only once every id has been handed out.
+            pub fn get_id_by_tag(&mut self, tag: String) -> DatabaseResult<u32> {
                 match self.map.get(&tag) {
-                    Some(id) => *id,
+                    Some(id) => Ok(*id),
                     None => {
-                        let newId = self.map.len() as u32;
+                        let newId = self.nextId;
+                        self.nextId = newId.checked_add(1).ok_or_else(|| {
+                            DatabaseError::invalid_input(format!("no tag id is left for {:?}", tag))
+                        })?;
                         self.map.insert(tag, newId);
-                        newId
+                        Ok(newId)
                     }
                 }
             }
+
+            /// Every tag as `(name, id)`, in no particular order.
+            pub fn iter(&self) -> impl Iterator<Item = (&str, u32)> {
+                self.map.iter().map(|(tag, id)| (tag.as_str(), *id))
+            }
+
+            pub fn get_id(&self, tag: &str) -> Option<u32> {
+                self.map.get(tag).cloned()
+            }
+
+            /// Gives the tag `from` the name `to`, keeping its id. Fails when `from` is unknown
+            /// or `to` is already taken; use `merge` for the latter.
+            pub fn rename(&mut self, from: &str, to: String) -> Option<u32> {
+                if self.map.contains_key(&to) {
+                    return None;
+                }
+                let id = self.map.remove(from)?;
+                self.map.insert(to, id);
+                Some(id)
+            }
+
+            /// Folds the tag `from` into the tag `into` and rewrites the tags of every game
+            /// accordingly. Returns the `(from, into)` ids on success.
+            pub fn merge<'a, T>(&mut self, from: &str, into: &str, games: T) -> Option<(u32, u32)>
+            where
+                T: Iterator<Item = &'a mut Game>,
+            {
+                if from == into {
+                    return None;
+                }
+                let intoId = *self.map.get(into)?;
+                let fromId = self.map.remove(from)?;
+                games.for_each(|game| game.replace_tag(fromId, intoId));
+                Some((fromId, intoId))
+            }
+
+            /// Drops every tag that no game refers to and returns their ids in order. The
+            /// other tags keep their ids, and the dropped ids are not handed out again, so
+            /// tag ids that clients hold never start to mean another tag.
+            pub fn compact<'a, T>(&mut self, games: T) -> Vec<u32>
+            where
+                T: Iterator<Item = &'a Game>,
+            {
+                let used: HashSet<u32> = games
+                    .filter_map(|game| game.tags.as_ref())
+                    .flat_map(|tags| tags.iter().cloned())
+                    .collect();
+                let mut dropped = Vec::new();
+                self.map.retain(|_, id| {
+                    let keep = used.contains(id);
+                    if !keep {
+                        dropped.push(*id);
+                    }
+                    keep
+                });
+                dropped.sort_unstable();
+                dropped
+            }
         }
-        impl Serialize for TagRegistry {
+
+        struct TagsById<'a>(&'a HashMap<String, u32>);
+        impl<'a> Serialize for TagsById<'a> {
             fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
             where
                 S: Serializer,
             {
-                let mut s = serializer.serialize_map(Some(self.map.len()))?;
-                for (key, val) in self.map.iter() {
+                let mut s = serializer.serialize_map(Some(self.0.len()))?;
+                for (key, val) in self.0.iter() {
                     s.serialize_entry(val, key)?;
                 }
                 s.end()
             }
         }
-        impl<'de> Deserialize<'de> for TagRegistry {
+        impl Serialize for TagRegistry {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                let mut s = serializer.serialize_struct("TagRegistry", 2)?;
+                s.serialize_field("nextId", &self.nextId)?;
+                s.serialize_field("tags", &TagsById(&self.map))?;
+                s.end()
+            }
+        }
+
+        struct TagsByName(HashMap<String, u32>);
+        impl<'de> Deserialize<'de> for TagsByName {
             fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
             where
                 D: Deserializer<'de>,
@@ -100,7 +226,9 @@ pub mod entity {
                     where
                         A: MapAccess<'de>,
                     {
-                        let mut values = HashMap::with_capacity(map.size_hint().unwrap_or(4096));
+                        // the length comes from the input, so it only sizes the first allocation
+                        let capacity = map.size_hint().unwrap_or(0).min(4096);
+                        let mut values = HashMap::with_capacity(capacity);
                         while let Some((key, value)) = map.next_entry()? {
                             values.insert(value, key); // swap
                         }
@@ -109,8 +237,37 @@ pub mod entity {
                 }
 
                 let visitor = MapVisitor {};
-                let map = deserializer.deserialize_map(visitor)?;
-                Ok(TagRegistry { map })
+                Ok(TagsByName(deserializer.deserialize_map(visitor)?))
+            }
+        }
+        impl<'de> Deserialize<'de> for TagRegistry {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                #[derive(Deserialize)]
+                struct Snapshot {
+                    nextId: u32,
+                    tags: TagsByName,
+                }
+
+                let Snapshot { nextId, tags } = Snapshot::deserialize(deserializer)?;
+                TagRegistry::restore(tags.0, nextId).map_err(de::Error::custom)
+            }
+        }
+
+        /// A `TagRegistry` in the layout of snapshots from before the id counter: a bare
+        /// `id -> name` map.
+        pub struct LegacyTagRegistry(pub TagRegistry);
+        impl<'de> Deserialize<'de> for LegacyTagRegistry {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                let tags = TagsByName::deserialize(deserializer)?;
+                TagRegistry::restore(tags.0, 0)
+                    .map(LegacyTagRegistry)
+                    .map_err(de::Error::custom)
             }
         }
     }
@@ -126,9 +283,97 @@ pub mod entity {
 
         #[derive(Serialize, Clone)]
         pub struct RecommendationScore {
-            pub up: u32,
-            pub down: u32,
-            pub sum: i32,
+            pub up: u64,
+            pub down: u64,
+            pub sum: i64,
+            /// Wilson lower bound of the positive share, between 0 and 1. Computed from the
+            /// weighted counts when the query has a decay.
+            pub wilson: f64,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub weighted: Option<WeightedScore>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub trend: Option<TrendScore>,
+        }
+
+        /// Review activity in the most recent window against the window right before it.
+        #[derive(Serialize, Clone)]
+        pub struct TrendScore {
+            pub recentRate: f64,
+            pub previousRate: f64,
+            pub recentPct: Option<f64>,
+            pub previousPct: Option<f64>,
+            /// Change of the review rate in percent of the previous rate.
+            pub rateChange: Option<f64>,
+            /// Change of the up-percentage in percentage points.
+            pub pctChange: Option<f64>,
+        }
+        impl TrendScore {
+            /// `recent` and `previous` are `(up, down)` counts over `days` days each.
+            pub fn new(days: u32, recent: (u64, u64), previous: (u64, u64)) -> Self {
+                let days = days.max(1) as f64;
+                let rate = |(up, down): (u64, u64)| (up + down) as f64 / days;
+                let pct = |(up, down): (u64, u64)| {
+                    if up + down > 0 {
+                        Some(up as f64 * 100.0 / (up + down) as f64)
+                    } else {
+                        None
+                    }
+                };
+                let (recentRate, previousRate) = (rate(recent), rate(previous));
+                let (recentPct, previousPct) = (pct(recent), pct(previous));
+                TrendScore {
+                    recentRate,
+                    previousRate,
+                    recentPct,
+                    previousPct,
+                    rateChange: if previousRate > 0.0 {
+                        Some((recentRate - previousRate) * 100.0 / previousRate)
+                    } else {
+                        None
+                    },
+                    pctChange: recentPct.and_then(|recent| Some(recent - previousPct?)),
+                }
+            }
+        }
+
+        /// Lower bound of the Wilson score interval for `positive` out of `total` at `z`.
+        /// Small samples are pulled towards 0, so 3 of 3 does not beat 950 of 1000.
+        pub fn wilson_lower_bound(positive: f64, total: f64, z: f64) -> f64 {
+            if total <= 0.0 {
+                return 0.0;
+            }
+            let p = positive / total;
+            let z2 = z * z;
+            let center = p + z2 / (2.0 * total);
+            let margin = z * (p * (1.0 - p) / total + z2 / (4.0 * total * total)).sqrt();
+            ((center - margin) / (1.0 + z2 / total)).max(0.0)
+        }
+
+        /// Review counts after time decay has been applied.
+        #[derive(Serialize, Clone)]
+        pub struct WeightedScore {
+            pub up: f64,
+            pub down: f64,
+            /// `up - down`, which the `Sum` threshold is checked against.
+            pub sum: f64,
+            /// `None` without any weighted reviews, like `SortKey::Pct` for plain counts.
+            pub pct: Option<f64>,
+        }
+        impl WeightedScore {
+            pub fn new(up: f64, down: f64) -> Self {
+                let total = up + down;
+                let pct = if total > 0.0 {
+                    Some(up * 100.0 / total)
+                } else {
+                    None
+                };
+                WeightedScore {
+                    up,
+                    down,
+                    sum: up - down,
+                    pct,
+                }
+            }
         }
     }
 }
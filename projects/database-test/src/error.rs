@@ -0,0 +1,86 @@
+use serde::Serialize;
+use std::fmt::{self, Display};
+
+/// Sent to JS as the first field of an error response, see `ValueKind::Error`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    /// No open database or prepared query has the given handle.
+    UnknownHandle = 1,
+    InvalidInput = 2,
+    Parse = 3,
+    Serialize = 4,
+    Overflow = 5,
+    /// `begin` inside a transaction, or `commit`/`rollback` outside of one.
+    Transaction = 6,
+    /// The database or query is in use by an export that has not returned yet.
+    Busy = 7,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DatabaseError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+pub type DatabaseResult<T> = Result<T, DatabaseError>;
+
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+impl std::error::Error for DatabaseError {}
+
+impl DatabaseError {
+    pub fn new<T>(code: ErrorCode, message: T) -> Self
+    where
+        T: Display,
+    {
+        DatabaseError {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn unknown_handle(handle: u32) -> Self {
+        Self::new(
+            ErrorCode::UnknownHandle,
+            format!("nothing is open under handle {}", handle),
+        )
+    }
+
+    pub fn invalid_input<T>(message: T) -> Self
+    where
+        T: Display,
+    {
+        Self::new(ErrorCode::InvalidInput, message)
+    }
+
+    pub fn parse<T>(error: T) -> Self
+    where
+        T: Display,
+    {
+        Self::new(ErrorCode::Parse, error)
+    }
+
+    pub fn serialize<T>(error: T) -> Self
+    where
+        T: Display,
+    {
+        Self::new(ErrorCode::Serialize, error)
+    }
+
+    pub fn busy(handle: u32) -> Self {
+        Self::new(
+            ErrorCode::Busy,
+            format!("handle {} is busy with another call", handle),
+        )
+    }
+
+    pub fn transaction<T>(message: T) -> Self
+    where
+        T: Display,
+    {
+        Self::new(ErrorCode::Transaction, message)
+    }
+}
@@ -0,0 +1,262 @@
+use crate::entity::entity::recommendation::RecommendationScore;
+use crate::entity::entity::tag::TagRegistry;
+use crate::error::{DatabaseError, DatabaseResult};
+use crate::query::query::game::GameQueryResult;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub enum ExportFormat {
+    /// One JSON array of rows.
+    Json,
+    /// A header line, then one line per row.
+    Csv,
+    /// One JSON object per line.
+    Ndjson,
+}
+impl ExportFormat {
+    /// The content type of an HTTP response in this format.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "application/json",
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+/// A column of an export. The score columns take the id of a date window and are empty for
+/// games without a score in that window. Their header shows the name of the window if the
+/// query gave it one, e.g. `up[launch month]`, and the id otherwise, e.g. `up[0]`.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub enum Column {
+    Id,
+    Name,
+    ReleaseDate,
+    Tags,
+    Up(u32),
+    Down(u32),
+    Sum(u32),
+    Wilson(u32),
+    WeightedPct(u32),
+}
+impl Column {
+    fn header(&self, names: &BTreeMap<u32, String>) -> String {
+        let label = |window: &u32| match names.get(window) {
+            Some(name) => name.clone(),
+            None => window.to_string(),
+        };
+        match self {
+            Column::Id => "id".to_string(),
+            Column::Name => "name".to_string(),
+            Column::ReleaseDate => "releaseDate".to_string(),
+            Column::Tags => "tags".to_string(),
+            Column::Up(window) => format!("up[{}]", label(window)),
+            Column::Down(window) => format!("down[{}]", label(window)),
+            Column::Sum(window) => format!("sum[{}]", label(window)),
+            Column::Wilson(window) => format!("wilson[{}]", label(window)),
+            Column::WeightedPct(window) => format!("weightedPct[{}]", label(window)),
+        }
+    }
+
+    fn value(&self, result: &GameQueryResult, tags: &HashMap<u32, &str>, names: bool) -> Value {
+        let score = |window: &u32| -> Option<&RecommendationScore> {
+            result.recommendations.as_ref()?.get(window)
+        };
+        match self {
+            Column::Id => result.id.into(),
+            Column::Name => result.name.as_str().into(),
+            Column::ReleaseDate => result.releaseDate.map_or(Value::Null, Value::from),
+            Column::Tags => match result.tags.as_ref() {
+                None => Value::Null,
+                Some(ids) if names => ids
+                    .iter()
+                    .map(|id| tags.get(id).map_or(Value::Null, |tag| (*tag).into()))
+                    .collect(),
+                Some(ids) => ids.iter().map(|id| Value::from(*id)).collect(),
+            },
+            Column::Up(window) => score(window).map_or(Value::Null, |s| s.up.into()),
+            Column::Down(window) => score(window).map_or(Value::Null, |s| s.down.into()),
+            Column::Sum(window) => score(window).map_or(Value::Null, |s| s.sum.into()),
+            Column::Wilson(window) => score(window).map_or(Value::Null, |s| s.wilson.into()),
+            Column::WeightedPct(window) => score(window)
+                .and_then(|s| s.weighted.as_ref())
+                .and_then(|w| w.pct)
+                .map_or(Value::Null, Value::from),
+        }
+    }
+}
+
+/// Reads a column from its header, e.g. `releaseDate` or `up[0]`. Windows are only taken
+/// by id here.
+impl FromStr for Column {
+    type Err = DatabaseError;
+    fn from_str(header: &str) -> DatabaseResult<Self> {
+        let unknown = || DatabaseError::invalid_input(format!("unknown column {:?}", header));
+        let (name, window) = match header.strip_suffix(']').and_then(|h| h.split_once('[')) {
+            Some((name, window)) => (name, Some(window.parse::<u32>().map_err(|_| unknown())?)),
+            None => (header, None),
+        };
+        Ok(match (name, window) {
+            ("id", None) => Column::Id,
+            ("name", None) => Column::Name,
+            ("releaseDate", None) => Column::ReleaseDate,
+            ("tags", None) => Column::Tags,
+            ("up", Some(window)) => Column::Up(window),
+            ("down", Some(window)) => Column::Down(window),
+            ("sum", Some(window)) => Column::Sum(window),
+            ("wilson", Some(window)) => Column::Wilson(window),
+            ("weightedPct", Some(window)) => Column::WeightedPct(window),
+            _ => return Err(unknown()),
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// The columns in order. Empty for id, name, release date, tags, and up and down for
+    /// every window that any of the results has a score in.
+    pub columns: Vec<Column>,
+    /// Write tag names instead of tag ids.
+    pub tagNames: bool,
+    /// Joins the tags of a game in CSV cells. Must not occur in any of the exported tags.
+    pub tagDelimiter: char,
+}
+impl Default for ExportOptions {
+    fn default() -> Self {
+        ExportOptions {
+            format: ExportFormat::Json,
+            columns: Vec::new(),
+            tagNames: true,
+            tagDelimiter: ';',
+        }
+    }
+}
+
+/// Writes `results` in the format and with the columns of `options`. `names` labels the
+/// windows in the headers, see `Column`.
+pub fn write_results(
+    results: &[GameQueryResult],
+    tags: &TagRegistry,
+    names: &BTreeMap<u32, String>,
+    options: &ExportOptions,
+) -> DatabaseResult<String> {
+    let columns = if options.columns.is_empty() {
+        default_columns(results)
+    } else {
+        options.columns.clone()
+    };
+    let tags: HashMap<u32, &str> = tags.iter().map(|(tag, id)| (id, tag)).collect();
+    let rows = results.iter().map(|result| {
+        columns
+            .iter()
+            .map(|column| column.value(result, &tags, options.tagNames))
+            .collect::<Vec<Value>>()
+    });
+
+    let mut out = String::new();
+    match options.format {
+        ExportFormat::Csv => {
+            let header: Vec<String> = columns.iter().map(|c| csv_cell(&c.header(names))).collect();
+            out.push_str(&header.join(","));
+            out.push_str("\r\n");
+            for row in rows {
+                let cells = row
+                    .iter()
+                    .map(|value| csv_text(value, options.tagDelimiter).map(|text| csv_cell(&text)))
+                    .collect::<DatabaseResult<Vec<String>>>()?;
+                out.push_str(&cells.join(","));
+                out.push_str("\r\n");
+            }
+        }
+        ExportFormat::Json | ExportFormat::Ndjson => {
+            let headers: Vec<String> = columns.iter().map(|c| c.header(names)).collect();
+            let objects = rows.map(|values| Row {
+                headers: &headers,
+                values,
+            });
+            if let ExportFormat::Json = options.format {
+                let objects: Vec<Row> = objects.collect();
+                out = serde_json::to_string(&objects).map_err(DatabaseError::serialize)?;
+            } else {
+                for object in objects {
+                    out.push_str(
+                        &serde_json::to_string(&object).map_err(DatabaseError::serialize)?,
+                    );
+                    out.push('\n');
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// A row as a JSON object that keeps the order of the columns.
+struct Row<'a> {
+    headers: &'a [String],
+    values: Vec<Value>,
+}
+impl Serialize for Row<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.values.len()))?;
+        for (header, value) in self.headers.iter().zip(self.values.iter()) {
+            map.serialize_entry(header, value)?;
+        }
+        map.end()
+    }
+}
+
+fn default_columns(results: &[GameQueryResult]) -> Vec<Column> {
+    let windows: BTreeSet<u32> = results
+        .iter()
+        .filter_map(|result| result.recommendations.as_ref())
+        .flat_map(|scores| scores.keys().cloned())
+        .collect();
+    let mut columns = vec![Column::Id, Column::Name, Column::ReleaseDate, Column::Tags];
+    for window in windows {
+        columns.push(Column::Up(window));
+        columns.push(Column::Down(window));
+    }
+    columns
+}
+
+/// Fails when a tag contains `tagDelimiter`: the CSV import splits the cell on it and has
+/// no way to escape it, so the file would not read back as the same tags.
+fn csv_text(value: &Value, tagDelimiter: char) -> DatabaseResult<String> {
+    Ok(match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        Value::Array(values) => {
+            let mut parts = Vec::with_capacity(values.len());
+            for value in values {
+                let part = csv_text(value, tagDelimiter)?;
+                if part.contains(tagDelimiter) {
+                    return Err(DatabaseError::invalid_input(format!(
+                        "tag {:?} contains the tag delimiter {:?}, export with another one",
+                        part, tagDelimiter
+                    )));
+                }
+                parts.push(part);
+            }
+            parts.join(&tagDelimiter.to_string())
+        }
+        _ => value.to_string(),
+    })
+}
+
+/// Quotes a cell when it would otherwise be read back differently.
+fn csv_cell(text: &str) -> String {
+    if text.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
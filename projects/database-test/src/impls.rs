@@ -1,17 +1,19 @@
 // use crate::database::DatabaseResponse;
+use crate::error::{DatabaseError, DatabaseResult};
+use crate::wasm_utils::LinerJavaScriptInput;
 use crate::wasm_utils::LinerJavaScriptOutput;
-use crate::LinerJavaScriptInput;
 use std::mem;
 
 pub enum ValueKind {
     Vec = 0,
     String = 1,
+    Error = 2,
 }
 
 impl<T> LinerJavaScriptInput for Vec<T> {
     type Liner = (usize, usize);
-    fn from_liner((ptr, len): Self::Liner) -> Vec<T> {
-        unsafe { Vec::<T>::from_raw_parts(ptr as *mut T, len, len) }
+    fn from_liner((ptr, len): Self::Liner) -> DatabaseResult<Vec<T>> {
+        Ok(unsafe { Vec::<T>::from_raw_parts(ptr as *mut T, len, len) })
     }
 }
 impl<T> LinerJavaScriptOutput for Vec<T>
@@ -27,8 +29,9 @@ where
 
 impl LinerJavaScriptInput for String {
     type Liner = (usize, usize);
-    fn from_liner((ptr, len): Self::Liner) -> String {
-        unsafe { String::from_raw_parts(ptr as *mut u8, len, len) }
+    fn from_liner((ptr, len): Self::Liner) -> DatabaseResult<String> {
+        let bytes = unsafe { Vec::<u8>::from_raw_parts(ptr as *mut u8, len, len) };
+        String::from_utf8(bytes).map_err(DatabaseError::parse)
     }
 }
 impl LinerJavaScriptOutput for String {
@@ -39,51 +42,193 @@ impl LinerJavaScriptOutput for String {
     }
 }
 
+impl LinerJavaScriptOutput for DatabaseError {
+    const KIND: u32 = ValueKind::Error as u32;
+    /// (code, message ptr, message len)
+    type Liner = (u32, usize, usize);
+    fn to_liner(&self) -> Self::Liner {
+        (
+            self.code as u32,
+            self.message.as_ptr() as usize,
+            self.message.len(),
+        )
+    }
+}
+
 pub mod query {
     pub mod id {
+        use crate::error::{DatabaseError, DatabaseResult};
         use crate::query::query::id::{FilterPolicy, IdFilterInput, QueryInput};
-        use crate::LinerJavaScriptInput;
+        use crate::wasm_utils::LinerJavaScriptInput;
         impl LinerJavaScriptInput for QueryInput {
             type Liner = (u32, u32, usize, usize);
-            fn from_liner((kind, policy, ptr, len): Self::Liner) -> Self {
-                let list = Vec::<u32>::from_liner((ptr, len));
-                let policy = match policy {
+            fn from_liner((kind, policy, ptr, len): Self::Liner) -> DatabaseResult<Self> {
+                let list = Vec::<u32>::from_liner((ptr, len))?;
+                let filterPolicy = match policy {
                     0 => Some(FilterPolicy::Include),
                     1 => Some(FilterPolicy::Exclude),
                     _ => None,
                 };
-                match (kind, policy) {
-                    (0, Some(policy)) => Self::GameId(IdFilterInput { policy, list }),
-                    (1, Some(policy)) => Self::TagId(IdFilterInput { policy, list }),
-                    _ => panic!(),
+                match (kind, filterPolicy) {
+                    (0, Some(policy)) => Ok(Self::GameId(IdFilterInput { policy, list })),
+                    (1, Some(policy)) => Ok(Self::TagId(IdFilterInput { policy, list })),
+                    _ => Err(DatabaseError::invalid_input(format!(
+                        "unknown id query (kind {}, policy {})",
+                        kind, policy
+                    ))),
                 }
             }
         }
     }
 
     pub mod recommendation {
+        use crate::error::{DatabaseError, DatabaseResult};
         use crate::query::query::recommendation::{
-            ComplexRangeInput, QueryInput, RangeFormat, SimpleRangeInput,
+            Bound, ComplexRangeInput, DecayInput, QueryInput, RangeFormat, SimpleRangeInput,
+            TrendInput, WindowedInput,
         };
-        use crate::LinerJavaScriptInput;
+        use crate::wasm_utils::LinerJavaScriptInput;
+
+        /// Unlike the other liners this one mixes 32 and 64 bit fields, so its layout is
+        /// pinned with `repr(C)`: six `u32`s, then two `i64`s.
+        #[repr(C)]
+        #[derive(Debug)]
+        pub struct QueryInputLiner {
+            kind: u32,
+            format: u32,
+            baseline: u32,
+            /// 0: unbounded, 1: inclusive, 2: exclusive
+            minBound: u32,
+            maxBound: u32,
+            window: u32,
+            min: i64,
+            max: i64,
+        }
+
+        fn to_bound(kind: u32, value: i64) -> DatabaseResult<Bound> {
+            match kind {
+                0 => Ok(Bound::Unbounded),
+                1 => Ok(Bound::Inclusive(value)),
+                2 => Ok(Bound::Exclusive(value)),
+                _ => Err(DatabaseError::invalid_input(format!(
+                    "unknown bound kind {}",
+                    kind
+                ))),
+            }
+        }
+
         impl LinerJavaScriptInput for QueryInput {
-            type Liner = (u32, u32, u32, i32, i32);
-            fn from_liner((kind, format, baseline, min, max): Self::Liner) -> Self {
+            type Liner = QueryInputLiner;
+            fn from_liner(liner: Self::Liner) -> DatabaseResult<Self> {
+                let QueryInputLiner {
+                    kind,
+                    format,
+                    baseline,
+                    ..
+                } = liner;
+                if kind == 5 {
+                    // { kind, format: halfLifeDays, baseline: anchor, .. }
+                    let input = Self::Decay(DecayInput {
+                        halfLifeDays: format,
+                        anchor: baseline,
+                    });
+                    return input.validate().map(|_| input);
+                }
+                if kind == 6 {
+                    // { kind, format: days, baseline: now, .. }
+                    let input = Self::Trend(TrendInput {
+                        days: format,
+                        now: baseline,
+                    });
+                    return input.validate().map(|_| input);
+                }
+                let formatKind = format;
                 let format = match format {
                     0 => None,
                     1 => Some(RangeFormat::Pct { baseline }),
                     2 => Some(RangeFormat::Count),
+                    // the low 16 bits are z in hundredths, e.g. 196 for 1.96, the high 16 bits
+                    // the baseline of the bounds, 0 for percent
+                    3 => Some(RangeFormat::Wilson {
+                        z: (baseline & 0xffff) as f64 / 100.0,
+                        baseline: match baseline >> 16 {
+                            0 => 100,
+                            scale => scale,
+                        },
+                    }),
                     _ => None,
                 };
-                let range = SimpleRangeInput { min, max };
-                match (kind, format) {
+                let range = SimpleRangeInput {
+                    min: to_bound(liner.minBound, liner.min)?,
+                    max: to_bound(liner.maxBound, liner.max)?,
+                };
+                let input = match (kind, format) {
                     (0, None) => Self::Date(range),
                     (1, None) => Self::Total(range),
+                    (7, None) => Self::TrendPct(range),
+                    (8, None) => Self::TrendRate(range),
+                    (9, None) => Self::SinceRelease(range),
                     (2, Some(format)) => Self::Up(ComplexRangeInput { format, range }),
                     (3, Some(format)) => Self::Down(ComplexRangeInput { format, range }),
-                    (4, Some(format)) => Self::Sum(ComplexRangeInput { format, range }),
-                    _ => panic!("Invalid Input"),
-                }
+                    (4, Some(format)) if !matches!(format, RangeFormat::Wilson { .. }) => {
+                        Self::Sum(ComplexRangeInput { format, range })
+                    }
+                    _ => {
+                        return Err(DatabaseError::invalid_input(format!(
+                            "unknown recommendation query (kind {}, format {})",
+                            kind, formatKind
+                        )))
+                    }
+                };
+                input.validate().map(|_| input)
+            }
+        }
+
+        impl LinerJavaScriptInput for WindowedInput {
+            type Liner = QueryInputLiner;
+            fn from_liner(liner: Self::Liner) -> DatabaseResult<Self> {
+                // windows from JavaScript go by their id alone
+                Ok(WindowedInput {
+                    window: liner.window,
+                    name: None,
+                    input: QueryInput::from_liner(liner)?,
+                })
+            }
+        }
+    }
+    pub mod sort {
+        use crate::error::{DatabaseError, DatabaseResult};
+        use crate::query::query::sort::{SortInput, SortKey, SortOrder};
+        use crate::wasm_utils::LinerJavaScriptInput;
+        impl LinerJavaScriptInput for SortInput {
+            type Liner = (u32, u32, u32);
+            fn from_liner((key, order, window): Self::Liner) -> DatabaseResult<Self> {
+                let invalid = || {
+                    DatabaseError::invalid_input(format!(
+                        "unknown sort (key {}, order {})",
+                        key, order
+                    ))
+                };
+                let key = match key {
+                    0 => SortKey::Id,
+                    1 => SortKey::ReleaseDate,
+                    2 => SortKey::Up,
+                    3 => SortKey::Down,
+                    4 => SortKey::Sum,
+                    5 => SortKey::Total,
+                    6 => SortKey::Pct,
+                    7 => SortKey::WeightedUp,
+                    8 => SortKey::WeightedDown,
+                    9 => SortKey::WeightedPct,
+                    10 => SortKey::Wilson,
+                    _ => return Err(invalid()),
+                };
+                let order = match order {
+                    0 => SortOrder::Asc,
+                    1 => SortOrder::Desc,
+                    _ => return Err(invalid()),
+                };
+                Ok(SortInput { key, order, window })
             }
         }
     }
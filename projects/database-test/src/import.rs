@@ -0,0 +1,777 @@
+pub mod steam {
+    use crate::entity::entity::recommendation::Recommendation;
+    use crate::error::{DatabaseError, DatabaseResult};
+    use serde::Deserialize;
+
+    /// The review histogram endpoint of the Steam store.
+    #[derive(Deserialize)]
+    struct Response {
+        success: i64,
+        /// Only a histogram on success; failed responses put anything here.
+        #[serde(default)]
+        results: serde_json::Value,
+    }
+
+    /// `weeks` and `recent` are left out: they cover stretches that `rollups` already
+    /// counts, and cannot be matched up with monthly rollups without splitting them.
+    #[derive(Deserialize)]
+    struct Histogram {
+        #[serde(default)]
+        rollups: Vec<Rollup>,
+    }
+
+    #[derive(Deserialize)]
+    struct Rollup {
+        date: u32,
+        recommendations_up: u32,
+        recommendations_down: u32,
+    }
+    impl From<Rollup> for Recommendation {
+        fn from(rollup: Rollup) -> Self {
+            Recommendation {
+                date: rollup.date,
+                up: rollup.recommendations_up,
+                down: rollup.recommendations_down,
+            }
+        }
+    }
+
+    /// Turns a raw histogram response into `Game.recommendations`, oldest first. Only
+    /// `rollups` are read, one per week or month as `rollup_type` says, which together
+    /// cover every review up to `end_date`; `weeks` and `recent` are ignored. Fails on a
+    /// histogram without rollups rather than returning no reviews at all.
+    pub fn parse_histogram(json: &str) -> DatabaseResult<Vec<Recommendation>> {
+        let response = serde_json::from_str::<Response>(json).map_err(DatabaseError::parse)?;
+        if response.success != 1 {
+            return Err(DatabaseError::invalid_input(format!(
+                "the histogram request did not succeed (success {})",
+                response.success
+            )));
+        }
+        let histogram =
+            serde_json::from_value::<Histogram>(response.results).map_err(DatabaseError::parse)?;
+        if histogram.rollups.is_empty() {
+            return Err(DatabaseError::invalid_input("the histogram has no rollups"));
+        }
+        let mut recommendations: Vec<Recommendation> = histogram
+            .rollups
+            .into_iter()
+            .map(Recommendation::from)
+            .collect();
+        recommendations.sort_by_key(|r| r.date);
+        Ok(recommendations)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const SAMPLE: &str = include_str!("../../json-test/data.json");
+
+        #[test]
+        fn reads_every_rollup_of_the_sample() {
+            let recommendations = parse_histogram(SAMPLE).unwrap();
+            assert_eq!(recommendations.len(), 61);
+            assert!(recommendations.windows(2).all(|w| w[0].date < w[1].date));
+            let first = &recommendations[0];
+            assert_eq!(
+                (first.date, first.up, first.down),
+                (1446336000, 14495, 4304)
+            );
+            let last = &recommendations[60];
+            assert_eq!((last.date, last.up, last.down), (1604188800, 177, 17));
+        }
+
+        #[test]
+        fn rejects_histograms_without_rollups() {
+            let json = r#"{"success": 1, "results": {"end_date": 1604275200, "rollups": [],
+                "weeks": [{"date": 1604188800, "recommendations_up": 1, "recommendations_down": 0}]}}"#;
+            assert!(parse_histogram(json).is_err());
+            assert!(parse_histogram(r#"{"success": 2, "results": []}"#).is_err());
+        }
+    }
+}
+
+pub mod csv {
+    use crate::entity::entity::game::GameInput;
+    use crate::error::{DatabaseError, DatabaseResult};
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+
+    /// Which columns hold which `GameInput` field, by header name. Columns that are not
+    /// mapped are ignored, and `tags` and `releaseDate` may be left unmapped.
+    #[derive(Serialize, Deserialize, Clone)]
+    #[serde(default)]
+    pub struct CsvMapping {
+        pub id: String,
+        pub name: String,
+        pub tags: Option<String>,
+        pub releaseDate: Option<String>,
+        pub delimiter: char,
+        /// Splits the tags cell; surrounding whitespace and empty tags are dropped.
+        pub tagDelimiter: char,
+        /// How dates like `01/02/2020` are read. Plain integers are always unix seconds.
+        pub dateOrder: DateOrder,
+    }
+    impl Default for CsvMapping {
+        fn default() -> Self {
+            CsvMapping {
+                id: "id".to_string(),
+                name: "name".to_string(),
+                tags: Some("tags".to_string()),
+                releaseDate: Some("releaseDate".to_string()),
+                delimiter: ',',
+                tagDelimiter: ';',
+                dateOrder: DateOrder::Ymd,
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Copy)]
+    pub enum DateOrder {
+        Ymd,
+        Dmy,
+        Mdy,
+    }
+
+    #[derive(Serialize)]
+    pub struct CsvImport {
+        pub games: Vec<GameInput>,
+        /// One entry per rejected row; the other rows are still in `games`.
+        pub errors: Vec<RowError>,
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct RowError {
+        /// The line the row starts on, counting the header as line 1.
+        pub line: usize,
+        pub column: Option<String>,
+        pub message: String,
+    }
+
+    /// Reads a catalog with a header row. Fails as a whole only when the header lacks a
+    /// mapped column or the file is not valid CSV; bad rows end up in `errors`, as do
+    /// rows with an empty name or an id that an earlier row already has.
+    pub fn parse_catalog(text: &str, mapping: &CsvMapping) -> DatabaseResult<CsvImport> {
+        let mut records = read_records(text, mapping.delimiter)?.into_iter();
+        let header = match records.next() {
+            Some((_, header)) => header,
+            None => return Err(DatabaseError::invalid_input("the CSV has no header row")),
+        };
+        let find = |column: &str| {
+            header
+                .iter()
+                .position(|name| name.trim() == column)
+                .ok_or_else(|| {
+                    DatabaseError::invalid_input(format!("the CSV has no column {:?}", column))
+                })
+        };
+        let idColumn = find(&mapping.id)?;
+        let nameColumn = find(&mapping.name)?;
+        let tagsColumn = mapping.tags.as_deref().map(find).transpose()?;
+        let dateColumn = mapping.releaseDate.as_deref().map(find).transpose()?;
+
+        let mut import = CsvImport {
+            games: Vec::new(),
+            errors: Vec::new(),
+        };
+        let mut seen = HashMap::<u32, usize>::new();
+        for (line, record) in records {
+            if record.iter().all(|cell| cell.trim().is_empty()) {
+                continue;
+            }
+            let error = |column: &str, message: String| RowError {
+                line,
+                column: Some(column.to_string()),
+                message,
+            };
+            if record.len() != header.len() {
+                import.errors.push(RowError {
+                    line,
+                    column: None,
+                    message: format!("expected {} cells, found {}", header.len(), record.len()),
+                });
+                continue;
+            }
+            let id = match record[idColumn].trim().parse::<u32>() {
+                Ok(id) => id,
+                Err(e) => {
+                    import.errors.push(error(&mapping.id, e.to_string()));
+                    continue;
+                }
+            };
+            if let Some(first) = seen.get(&id) {
+                let message = format!("is the id of the row on line {} already", first);
+                import.errors.push(error(&mapping.id, message));
+                continue;
+            }
+            let name = record[nameColumn].trim();
+            if name.is_empty() {
+                import
+                    .errors
+                    .push(error(&mapping.name, "is empty".to_string()));
+                continue;
+            }
+            let releaseDate = match dateColumn.map(|column| record[column].trim()) {
+                None | Some("") => None,
+                Some(cell) => match parse_date(cell, mapping.dateOrder) {
+                    Ok(date) => Some(date),
+                    Err(message) => {
+                        let column = mapping.releaseDate.as_deref().unwrap_or_default();
+                        import.errors.push(error(column, message));
+                        continue;
+                    }
+                },
+            };
+            let tags = tagsColumn.map(|column| {
+                record[column]
+                    .split(mapping.tagDelimiter)
+                    .map(str::trim)
+                    .filter(|tag| !tag.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            });
+            seen.insert(id, line);
+            import.games.push(GameInput {
+                id,
+                name: name.to_string(),
+                tags,
+                releaseDate,
+                recommendations: None,
+            });
+        }
+        Ok(import)
+    }
+
+    /// Splits `text` into records of cells, each with the line it starts on. Handles
+    /// quoted cells with delimiters, doubled quotes and line breaks in them.
+    fn read_records(text: &str, delimiter: char) -> DatabaseResult<Vec<(usize, Vec<String>)>> {
+        let mut records = Vec::new();
+        let mut record = Vec::new();
+        let mut cell = String::new();
+        let mut line = 1;
+        let mut start = 1;
+        let mut quoted = false;
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            if quoted {
+                match c {
+                    '"' if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        cell.push('"');
+                    }
+                    '"' => quoted = false,
+                    _ => {
+                        if c == '\n' {
+                            line += 1;
+                        }
+                        cell.push(c);
+                    }
+                }
+                continue;
+            }
+            match c {
+                // spaces after the delimiter, as in `1, "a,b"`, don't keep the quote from
+                // opening
+                '"' if cell.trim().is_empty() => {
+                    cell.clear();
+                    quoted = true;
+                }
+                '\r' if chars.peek() == Some(&'\n') => (),
+                '\n' => {
+                    record.push(std::mem::take(&mut cell));
+                    records.push((start, std::mem::take(&mut record)));
+                    line += 1;
+                    start = line;
+                }
+                _ if c == delimiter => record.push(std::mem::take(&mut cell)),
+                _ => cell.push(c),
+            }
+        }
+        if quoted {
+            return Err(DatabaseError::parse(format!(
+                "unterminated quote in the row on line {}",
+                start
+            )));
+        }
+        if !cell.is_empty() || !record.is_empty() {
+            record.push(cell);
+            records.push((start, record));
+        }
+        Ok(records)
+    }
+
+    /// Reads unix seconds, or a date in `order` with `-`, `/` or `.` between its parts,
+    /// optionally followed by ` HH:MM[:SS]` or `THH:MM[:SS]`, in UTC.
+    fn parse_date(cell: &str, order: DateOrder) -> Result<u32, String> {
+        if let Ok(seconds) = cell.parse::<u32>() {
+            return Ok(seconds);
+        }
+        let invalid = || format!("cannot read {:?} as a date", cell);
+        let (date, time) = match cell.find([' ', 'T']) {
+            Some(at) => (&cell[..at], Some(cell[at + 1..].trim())),
+            None => (cell, None),
+        };
+        // a trailing `Z` marks UTC, which every time is read as anyway
+        let time = time.map(|time| time.strip_suffix('Z').unwrap_or(time));
+        let parts: Vec<u32> = date
+            .split(['-', '/', '.'])
+            .map(|part| part.parse::<u32>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        let (year, month, day) = match (order, parts.as_slice()) {
+            (DateOrder::Ymd, [y, m, d]) => (*y, *m, *d),
+            (DateOrder::Dmy, [d, m, y]) => (*y, *m, *d),
+            (DateOrder::Mdy, [m, d, y]) => (*y, *m, *d),
+            _ => return Err(invalid()),
+        };
+        if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+            return Err(invalid());
+        }
+        let seconds = match time {
+            None => 0,
+            Some(time) => {
+                let parts: Vec<u32> = time
+                    .split(':')
+                    .map(|part| part.parse::<u32>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid())?;
+                match parts.as_slice() {
+                    [h, m] if *h < 24 && *m < 60 => h * 3600 + m * 60,
+                    [h, m, s] if *h < 24 && *m < 60 && *s < 60 => h * 3600 + m * 60 + s,
+                    _ => return Err(invalid()),
+                }
+            }
+        };
+        let days = days_from_civil(year as i64, month, day);
+        let seconds = days * 86400 + seconds as i64;
+        u32::try_from(seconds).map_err(|_| format!("{:?} is outside of the unix time range", cell))
+    }
+
+    fn days_in_month(year: u32, month: u32) -> u32 {
+        match month {
+            2 if year.is_multiple_of(4)
+                && (!year.is_multiple_of(100) || year.is_multiple_of(400)) =>
+            {
+                29
+            }
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    /// Days since 1970-01-01 of a proleptic Gregorian date.
+    fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let yearOfEra = year - era * 400;
+        let month = month as i64;
+        let dayOfYear =
+            (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+        let dayOfEra = yearOfEra * 365 + yearOfEra / 4 - yearOfEra / 100 + dayOfYear;
+        era * 146097 + dayOfEra - 719468
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn cells(text: &str) -> Vec<Vec<String>> {
+            read_records(text, ',')
+                .unwrap()
+                .into_iter()
+                .map(|(_, record)| record)
+                .collect()
+        }
+
+        #[test]
+        fn splits_records_and_cells() {
+            let cases: &[(&str, &[&[&str]])] = &[
+                ("a,b\nc,d", &[&["a", "b"], &["c", "d"]]),
+                ("a,b\r\nc,d\r\n", &[&["a", "b"], &["c", "d"]]),
+                ("\"a,b\",c", &[&["a,b", "c"]]),
+                ("1, \"a,b\"", &[&["1", "a,b"]]),
+                ("\"say \"\"hi\"\"\",x", &[&["say \"hi\"", "x"]]),
+                (
+                    "\"two\nlines\",x\ny,z",
+                    &[&["two\nlines", "x"], &["y", "z"]],
+                ),
+                ("\"\",", &[&["", ""]]),
+                ("a\"b,c", &[&["a\"b", "c"]]),
+            ];
+            for (text, expected) in cases {
+                assert_eq!(cells(text), *expected, "{:?}", text);
+            }
+        }
+
+        #[test]
+        fn counts_lines_across_quoted_line_breaks() {
+            let records = read_records("h\n\"a\nb\"\nc", ',').unwrap();
+            let lines: Vec<usize> = records.iter().map(|(line, _)| *line).collect();
+            assert_eq!(lines, [1, 2, 4]);
+            assert!(read_records("\"open", ',').is_err());
+        }
+
+        #[test]
+        fn reads_dates() {
+            let cases: &[(&str, DateOrder, Option<u32>)] = &[
+                ("1577923200", DateOrder::Ymd, Some(1577923200)),
+                ("2020-01-02", DateOrder::Ymd, Some(1577923200)),
+                ("02/01/2020", DateOrder::Dmy, Some(1577923200)),
+                ("01/02/2020", DateOrder::Mdy, Some(1577923200)),
+                ("02.01.2020", DateOrder::Dmy, Some(1577923200)),
+                ("2020-01-02 10:00", DateOrder::Ymd, Some(1577959200)),
+                ("2020-01-02T10:00:00", DateOrder::Ymd, Some(1577959200)),
+                ("2020-01-02T10:00:00Z", DateOrder::Ymd, Some(1577959200)),
+                ("2020-01-02T10:00:00ZZ", DateOrder::Ymd, None),
+                ("2020-02-29", DateOrder::Ymd, Some(1582934400)),
+                ("2000-02-29", DateOrder::Ymd, Some(951782400)),
+                ("2019-02-29", DateOrder::Ymd, None),
+                ("1900-02-29", DateOrder::Ymd, None),
+                ("2020-04-31", DateOrder::Ymd, None),
+                ("2020-13-01", DateOrder::Ymd, None),
+                ("2020-01-02T24:00", DateOrder::Ymd, None),
+                ("1969-12-31", DateOrder::Ymd, None),
+                ("1970-01-01", DateOrder::Ymd, Some(0)),
+                ("2106-02-07T06:28:15", DateOrder::Ymd, Some(u32::MAX)),
+                ("2106-02-07T06:28:16", DateOrder::Ymd, None),
+            ];
+            for (cell, order, expected) in cases {
+                assert_eq!(parse_date(cell, *order).ok(), *expected, "{:?}", cell);
+            }
+        }
+
+        #[test]
+        fn rejects_empty_names_and_repeated_ids() {
+            let mapping = CsvMapping {
+                tags: None,
+                releaseDate: None,
+                ..CsvMapping::default()
+            };
+            let text = "id,name\n1,a\n2, \n1,b\n3,c\n2,d\n";
+            let import = parse_catalog(text, &mapping).unwrap();
+            let ids: Vec<u32> = import.games.iter().map(|game| game.id).collect();
+            assert_eq!(ids, [1, 3, 2]);
+            let errors: Vec<(usize, Option<&str>)> = import
+                .errors
+                .iter()
+                .map(|error| (error.line, error.column.as_deref()))
+                .collect();
+            assert_eq!(errors, [(3, Some("name")), (4, Some("id"))]);
+        }
+    }
+}
+
+pub mod ndjson {
+    use super::json::{as_u32, check_record};
+    use crate::entity::entity::game::GameInput;
+    use serde::Serialize;
+    use serde_json::Value;
+    use std::collections::HashMap;
+
+    /// At most this many `LineError`s are kept; the rest are only counted.
+    const MAX_ERRORS: usize = 100;
+
+    #[derive(Serialize, Clone, Default)]
+    pub struct IngestProgress {
+        pub bytes: u64,
+        pub lines: u64,
+        pub games: u64,
+        /// Lines that could not be read as a `GameInput` or that `json::parse_games` would
+        /// reject as a record.
+        pub failed: u64,
+        /// The first of the failed lines.
+        pub errors: Vec<LineError>,
+    }
+
+    #[derive(Serialize, Clone)]
+    pub struct LineError {
+        pub line: u64,
+        pub message: String,
+    }
+
+    /// Reads `GameInput`s from newline-delimited JSON handed over in chunks of any size,
+    /// checking each record like `json::parse_games` does, with ids repeated across the
+    /// whole ingest. Only the unfinished last line of a chunk is kept until the next one,
+    /// so besides the ids seen so far memory use is bounded by the longest record rather
+    /// than by the whole catalog.
+    #[derive(Default)]
+    pub struct NdjsonIngest {
+        pending: Vec<u8>,
+        progress: IngestProgress,
+        /// The line each id was read from.
+        seen: HashMap<u32, u64>,
+    }
+    impl NdjsonIngest {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Reads every line that `chunk` completes. Lines that fail to parse are skipped
+        /// and recorded in the progress.
+        pub fn feed(&mut self, chunk: &[u8]) -> Vec<GameInput> {
+            self.progress.bytes += chunk.len() as u64;
+            let mut games = Vec::new();
+            let mut rest = chunk;
+            while let Some(end) = rest.iter().position(|b| *b == b'\n') {
+                if self.pending.is_empty() {
+                    self.read_line(&rest[..end], &mut games);
+                } else {
+                    let mut line = std::mem::take(&mut self.pending);
+                    line.extend_from_slice(&rest[..end]);
+                    self.read_line(&line, &mut games);
+                }
+                rest = &rest[end + 1..];
+            }
+            self.pending.extend_from_slice(rest);
+            games
+        }
+
+        /// Reads the last line if the input did not end with a line break.
+        pub fn finish(&mut self) -> Vec<GameInput> {
+            let mut games = Vec::new();
+            let line = std::mem::take(&mut self.pending);
+            if !line.is_empty() {
+                self.read_line(&line, &mut games);
+            }
+            games
+        }
+
+        pub fn progress(&self) -> &IngestProgress {
+            &self.progress
+        }
+
+        fn read_line(&mut self, line: &[u8], games: &mut Vec<GameInput>) {
+            self.progress.lines += 1;
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.iter().all(u8::is_ascii_whitespace) {
+                return;
+            }
+            let record = match serde_json::from_slice::<Value>(line) {
+                Ok(record) => record,
+                Err(e) => return self.fail(vec![e.to_string()]),
+            };
+            let mut problems = Vec::new();
+            match record.as_object() {
+                None => problems.push("expected an object".to_string()),
+                Some(fields) => {
+                    check_record(fields, &mut |path: &str, message: String| {
+                        problems.push(format!("{}: {}", path, message))
+                    });
+                    if let Some(first) = fields
+                        .get("id")
+                        .and_then(as_u32)
+                        .and_then(|id| self.seen.get(&id))
+                    {
+                        problems.push(format!("id: is the id of line {} already", first));
+                    }
+                }
+            }
+            if !problems.is_empty() {
+                return self.fail(problems);
+            }
+            match serde_json::from_value::<GameInput>(record) {
+                Ok(game) => {
+                    self.seen.insert(game.id, self.progress.lines);
+                    self.progress.games += 1;
+                    games.push(game);
+                }
+                Err(e) => self.fail(vec![e.to_string()]),
+            }
+        }
+
+        fn fail(&mut self, problems: Vec<String>) {
+            let progress = &mut self.progress;
+            progress.failed += 1;
+            if progress.errors.len() < MAX_ERRORS {
+                progress.errors.push(LineError {
+                    line: progress.lines,
+                    message: problems.join("; "),
+                });
+            }
+        }
+    }
+}
+
+pub mod json {
+    use crate::entity::entity::game::GameInput;
+    use crate::error::{DatabaseError, DatabaseResult};
+    use serde::Serialize;
+    use serde_json::{Map, Value};
+    use std::collections::HashMap;
+
+    pub struct GameBatch {
+        pub games: Vec<GameInput>,
+        /// Every problem of every rejected record; the other records are in `games`.
+        pub errors: Vec<RecordError>,
+    }
+
+    #[derive(Serialize, Debug)]
+    pub struct RecordError {
+        /// The position of the record in the batch.
+        pub index: usize,
+        /// Where in the record the problem is, e.g. `recommendations[2].date`, or empty for
+        /// the record as a whole.
+        pub path: String,
+        pub message: String,
+    }
+
+    /// Reads a JSON array of `GameInput`s record by record, so that one bad record does
+    /// not cost the whole batch. Besides the shape of each record this rejects empty
+    /// names, ids that an earlier record of the batch already has, and recommendations
+    /// that are not in strictly increasing date order. Dates are only checked to be unix
+    /// seconds that fit a `u32`; there is no range of plausible dates. Fails as a whole
+    /// only when `json` is not an array.
+    pub fn parse_games(json: &str) -> DatabaseResult<GameBatch> {
+        let records: Vec<Value> = serde_json::from_str(json).map_err(DatabaseError::parse)?;
+        let mut batch = GameBatch {
+            games: Vec::with_capacity(records.len()),
+            errors: Vec::new(),
+        };
+        let mut seen = HashMap::<u32, usize>::new();
+        for (index, record) in records.into_iter().enumerate() {
+            let mut errors = Vec::new();
+            let mut error = |path: &str, message: String| {
+                errors.push(RecordError {
+                    index,
+                    path: path.to_string(),
+                    message,
+                })
+            };
+            let fields = match record.as_object() {
+                Some(fields) => fields,
+                None => {
+                    error("", "expected an object".to_string());
+                    batch.errors.append(&mut errors);
+                    continue;
+                }
+            };
+            check_record(fields, &mut error);
+            if let Some(first) = fields
+                .get("id")
+                .and_then(as_u32)
+                .and_then(|id| seen.get(&id))
+            {
+                error("id", format!("is the id of record {} already", first));
+            }
+            if !errors.is_empty() {
+                batch.errors.append(&mut errors);
+                continue;
+            }
+            match serde_json::from_value::<GameInput>(record) {
+                Ok(game) => {
+                    seen.insert(game.id, index);
+                    batch.games.push(game);
+                }
+                Err(e) => batch.errors.push(RecordError {
+                    index,
+                    path: String::new(),
+                    message: e.to_string(),
+                }),
+            }
+        }
+        Ok(batch)
+    }
+
+    /// The checks of `parse_games` that concern one record alone, shared with
+    /// `ndjson::NdjsonIngest`.
+    pub(super) fn check_record<E>(fields: &Map<String, Value>, error: &mut E)
+    where
+        E: FnMut(&str, String),
+    {
+        match fields.get("id") {
+            None | Some(Value::Null) => error("id", "is missing".to_string()),
+            Some(id) => check_u32(id, "id", error),
+        }
+        match fields.get("name") {
+            Some(Value::String(name)) if name.trim().is_empty() => {
+                error("name", "is empty".to_string())
+            }
+            Some(Value::String(_)) => (),
+            None | Some(Value::Null) => error("name", "is missing".to_string()),
+            Some(_) => error("name", "expected a string".to_string()),
+        }
+        match fields.get("tags") {
+            None | Some(Value::Null) => (),
+            Some(Value::Array(tags)) => {
+                for (i, tag) in tags.iter().enumerate() {
+                    if !tag.is_string() {
+                        error(&format!("tags[{}]", i), "expected a string".to_string());
+                    }
+                }
+            }
+            Some(_) => error("tags", "expected an array".to_string()),
+        }
+        match fields.get("releaseDate") {
+            None | Some(Value::Null) => (),
+            Some(date) => check_u32(date, "releaseDate", error),
+        }
+        let recommendations = match fields.get("recommendations") {
+            None | Some(Value::Null) => return,
+            Some(Value::Array(recommendations)) => recommendations,
+            Some(_) => return error("recommendations", "expected an array".to_string()),
+        };
+        let mut previous: Option<u32> = None;
+        for (i, recommendation) in recommendations.iter().enumerate() {
+            let path = |field: &str| format!("recommendations[{}]{}", i, field);
+            let fields = match recommendation.as_object() {
+                Some(fields) => fields,
+                None => {
+                    error(&path(""), "expected an object".to_string());
+                    continue;
+                }
+            };
+            for field in ["up", "down"].iter() {
+                match fields.get(*field) {
+                    None => error(&path(&format!(".{}", field)), "is missing".to_string()),
+                    Some(count) => check_u32(count, &path(&format!(".{}", field)), error),
+                }
+            }
+            let date = match fields.get("date") {
+                None => {
+                    error(&path(".date"), "is missing".to_string());
+                    continue;
+                }
+                Some(date) => date,
+            };
+            check_u32(date, &path(".date"), error);
+            let date = match as_u32(date) {
+                Some(date) => date,
+                None => continue,
+            };
+            match previous {
+                Some(previous) if date == previous => {
+                    error(&path(".date"), format!("{} is there already", date))
+                }
+                Some(previous) if date < previous => error(
+                    &path(".date"),
+                    format!("{} comes before the date {} before it", date, previous),
+                ),
+                _ => (),
+            }
+            previous = Some(previous.map_or(date, |previous| previous.max(date)));
+        }
+    }
+
+    fn check_u32<E>(value: &Value, path: &str, error: &mut E)
+    where
+        E: FnMut(&str, String),
+    {
+        if as_u32(value).is_none() {
+            let message = match value {
+                Value::Number(_) => format!("{} is not between 0 and {}", value, u32::MAX),
+                _ => format!("expected an integer, found {}", value),
+            };
+            error(path, message);
+        }
+    }
+
+    pub(super) fn as_u32(value: &Value) -> Option<u32> {
+        value
+            .as_u64()
+            .filter(|n| *n <= u32::MAX as u64)
+            .map(|n| n as u32)
+    }
+}
@@ -0,0 +1,113 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// The upper bounds of the latency buckets in milliseconds; the last bucket takes the rest.
+const BUCKETS_MS: [f64; 10] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0];
+
+/// The entry points that run a query.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum QueryKind {
+    Filter,
+    Explain,
+    Export,
+}
+
+/// Counters of one database since it was opened or last reset. They live next to the
+/// catalog rather than in it: snapshots leave them out and `rollback` keeps them.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    queries: BTreeMap<QueryKind, u64>,
+    cacheHits: u64,
+    ingest: IngestMetrics,
+    latency: BTreeMap<QueryKind, Histogram>,
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct IngestMetrics {
+    /// Calls of `upsert_games`, e.g. one per `updateGames` or per ingest chunk.
+    pub batches: u64,
+    pub games: u64,
+    pub deleted: u64,
+    /// Games whose recommendations were replaced, e.g. from a Steam histogram.
+    pub recommendations: u64,
+}
+
+/// Latencies in milliseconds; `counts[i]` holds those up to `upperBoundsMs[i]`, and the
+/// last count those above every bound.
+#[derive(Serialize, Clone)]
+pub struct Histogram {
+    pub upperBoundsMs: Vec<f64>,
+    pub counts: Vec<u64>,
+    pub count: u64,
+    pub sumMs: f64,
+    pub maxMs: f64,
+}
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            upperBoundsMs: BUCKETS_MS.to_vec(),
+            counts: vec![0; BUCKETS_MS.len() + 1],
+            count: 0,
+            sumMs: 0.0,
+            maxMs: 0.0,
+        }
+    }
+}
+impl Histogram {
+    fn record(&mut self, ms: f64) {
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(BUCKETS_MS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sumMs += ms;
+        self.maxMs = self.maxMs.max(ms);
+    }
+}
+
+#[derive(Serialize)]
+pub struct MetricsReport {
+    pub queries: BTreeMap<QueryKind, u64>,
+    /// `Filter` and `Export` queries answered from the result cache.
+    pub cacheHits: u64,
+    pub ingest: IngestMetrics,
+    /// Totals at the time of the report.
+    pub games: u64,
+    pub tags: u64,
+    /// Only filled in when the database has a clock, see `Database::set_clock`.
+    pub latency: BTreeMap<QueryKind, Histogram>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a query and, if it was timed, its latency.
+    pub fn query(&mut self, kind: QueryKind, elapsedMs: Option<f64>) {
+        *self.queries.entry(kind).or_default() += 1;
+        if let Some(ms) = elapsedMs {
+            self.latency.entry(kind).or_default().record(ms);
+        }
+    }
+
+    pub fn cache_hit(&mut self) {
+        self.cacheHits += 1;
+    }
+
+    pub fn ingest(&mut self) -> &mut IngestMetrics {
+        &mut self.ingest
+    }
+
+    pub fn report(&self, games: u64, tags: u64) -> MetricsReport {
+        MetricsReport {
+            queries: self.queries.clone(),
+            cacheHits: self.cacheHits,
+            ingest: self.ingest.clone(),
+            games,
+            tags,
+            latency: self.latency.clone(),
+        }
+    }
+}
@@ -1,55 +1,332 @@
 pub mod query {
     pub mod game {
-        use super::id::IdQuery;
-        use super::recommendation::RecommendationQuery;
+        use super::id::{IdQuery, QueryInput as IdQueryInput};
+        use super::recommendation::{WindowedInput, WindowedQuery};
+        use super::sort::{SortInput, SortQuery};
+        use crate::entity::entity::recommendation::RecommendationScore;
+        use crate::error::{DatabaseError, DatabaseResult};
+        use serde::{Deserialize, Serialize};
+        use std::collections::BTreeMap;
 
+        #[derive(Serialize, Clone)]
         pub struct GameQuery {
             pub idQuery: IdQuery,
-            pub recommendationQuery: RecommendationQuery,
+            pub recommendationQuery: WindowedQuery,
+            pub sortQuery: SortQuery,
+        }
+        impl Default for GameQuery {
+            fn default() -> Self {
+                Self::new()
+            }
         }
         impl GameQuery {
             pub fn new() -> Self {
                 GameQuery {
                     idQuery: IdQuery::new(),
-                    recommendationQuery: RecommendationQuery::new(),
+                    recommendationQuery: WindowedQuery::new(),
+                    sortQuery: SortQuery::new(),
+                }
+            }
+
+            /// Canonical form of the query: queries with the same key select and order the
+            /// same games, whatever order or duplicates their inputs came in.
+            pub fn key(&self) -> DatabaseResult<String> {
+                serde_json::to_string(self).map_err(DatabaseError::serialize)
+            }
+
+            /// Swaps in new parameters for a query that is run repeatedly, see
+            /// `WindowedQuery::bind`.
+            pub fn bind<T>(&mut self, recommendationInputs: T) -> DatabaseResult<()>
+            where
+                T: Iterator<Item = WindowedInput>,
+            {
+                self.recommendationQuery.bind(recommendationInputs)
+            }
+        }
+
+        /// A whole query as JSON, for callers that do not go through the wasm liners. Every
+        /// field may be left out.
+        #[derive(Serialize, Deserialize, Default)]
+        #[serde(default)]
+        pub struct GameQueryInput {
+            pub ids: Vec<IdQueryInput>,
+            pub recommendations: Vec<WindowedInput>,
+            pub sort: Vec<SortInput>,
+        }
+        impl GameQueryInput {
+            pub fn into_query(self) -> DatabaseResult<GameQuery> {
+                let mut query = GameQuery::new();
+                query.idQuery.build(self.ids.into_iter());
+                query
+                    .recommendationQuery
+                    .build(self.recommendations.into_iter())?;
+                query.sortQuery.build(self.sort.into_iter());
+                Ok(query)
+            }
+        }
+
+        #[derive(Serialize, Clone)]
+        pub struct GameQueryResult {
+            pub id: u32,
+            pub name: String,
+            pub tags: Option<Vec<u32>>,
+            pub releaseDate: Option<u32>,
+            /// One score per date window, keyed by the window id.
+            pub recommendations: Option<BTreeMap<u32, RecommendationScore>>,
+        }
+    }
+    pub mod explain {
+        use serde::Serialize;
+        use std::collections::BTreeMap;
+
+        /// A step of a query that can reject a game, in the order the steps run.
+        #[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+        pub enum Stage {
+            GameId,
+            TagId,
+            TrendPct,
+            TrendRate,
+            /// Games without a release date cannot pass `SinceRelease`.
+            ReleaseDate,
+            /// `Date` and `SinceRelease` filtering. Counts recommendation buckets, not games.
+            DateBucket,
+            Total,
+            Up,
+            Down,
+            Sum,
+        }
+
+        #[derive(Serialize, Clone)]
+        pub struct StageStats {
+            pub stage: Stage,
+            /// The date window of a recommendation stage.
+            pub window: Option<u32>,
+            pub examined: u64,
+            pub rejected: u64,
+        }
+
+        /// What a query did, see `Database::explain`.
+        #[derive(Serialize, Clone)]
+        pub struct QueryExplain {
+            pub scanned: u64,
+            pub matched: u64,
+            /// Only the stages the query actually has; a game that is rejected by one
+            /// stage is not examined by the later ones.
+            pub stages: Vec<StageStats>,
+            /// Indexes the query was answered with. There are none yet, so every query
+            /// scans all games.
+            pub indexes: Vec<String>,
+            /// Whether the result cache holds the results of this query.
+            pub cached: bool,
+            /// As measured by the clock given to `Database::explain`.
+            pub elapsedMs: f64,
+        }
+
+        #[derive(Default)]
+        pub struct StageCounts {
+            counts: BTreeMap<(Option<u32>, Stage), (u64, u64)>,
+        }
+        impl StageCounts {
+            pub fn new() -> Self {
+                StageCounts {
+                    counts: BTreeMap::new(),
                 }
             }
+
+            pub fn add(&mut self, window: Option<u32>, stage: Stage, examined: u64, rejected: u64) {
+                let counts = self.counts.entry((window, stage)).or_insert((0, 0));
+                counts.0 += examined;
+                counts.1 += rejected;
+            }
+
+            pub fn into_stats(self) -> Vec<StageStats> {
+                self.counts
+                    .into_iter()
+                    .map(|((window, stage), (examined, rejected))| StageStats {
+                        stage,
+                        window,
+                        examined,
+                        rejected,
+                    })
+                    .collect()
+            }
+        }
+
+        /// Counts one game looked at by `stage` when counting at all, and hands back
+        /// `passed`, so that the checks read the same with and without counting.
+        pub fn record(
+            counts: &mut Option<&mut StageCounts>,
+            window: Option<u32>,
+            stage: Stage,
+            passed: bool,
+        ) -> bool {
+            if let Some(counts) = counts {
+                counts.add(window, stage, 1, if passed { 0 } else { 1 });
+            }
+            passed
+        }
+    }
+    pub mod sort {
+        use super::game::GameQueryResult;
+        use crate::entity::entity::recommendation::RecommendationScore;
+        use serde::{Deserialize, Serialize};
+        use std::cmp::Ordering;
+
+        #[derive(Serialize, Deserialize, Clone)]
+        pub struct SortInput {
+            pub key: SortKey,
+            pub order: SortOrder,
+            /// The date window whose score the recommendation keys are read from.
+            pub window: u32,
+        }
+
+        #[derive(Serialize, Deserialize, Clone)]
+        pub enum SortKey {
+            Id,
+            ReleaseDate,
+            Up,
+            Down,
+            Sum,
+            Total,
+            Pct,
+            WeightedUp,
+            WeightedDown,
+            WeightedPct,
+            Wilson,
+        }
+
+        #[derive(Serialize, Deserialize, Clone)]
+        pub enum SortOrder {
+            Asc,
+            Desc,
+        }
+
+        /// Orders results by the given keys, earlier keys taking precedence. Results that
+        /// have no value for a key always come after the ones that do.
+        #[derive(Serialize, Clone)]
+        pub struct SortQuery {
+            inputs: Vec<SortInput>,
+        }
+
+        impl SortKey {
+            fn value(&self, result: &GameQueryResult, window: u32) -> Option<f64> {
+                let score = || result.recommendations.as_ref()?.get(&window);
+                let weighted = || score().and_then(|score| score.weighted.as_ref());
+                match self {
+                    SortKey::Id => Some(result.id as f64),
+                    SortKey::ReleaseDate => result.releaseDate.map(|date| date as f64),
+                    SortKey::Up => score().map(|score| score.up as f64),
+                    SortKey::Down => score().map(|score| score.down as f64),
+                    SortKey::Sum => score().map(|score| score.sum as f64),
+                    SortKey::Total => score().map(|score| score.up as f64 + score.down as f64),
+                    SortKey::Pct => score().and_then(pct),
+                    SortKey::WeightedUp => weighted().map(|weighted| weighted.up),
+                    SortKey::WeightedDown => weighted().map(|weighted| weighted.down),
+                    SortKey::WeightedPct => weighted().and_then(|weighted| weighted.pct),
+                    SortKey::Wilson => score().map(|score| score.wilson),
+                }
+            }
+        }
+
+        fn pct(score: &RecommendationScore) -> Option<f64> {
+            let total = score.up as f64 + score.down as f64;
+            if total > 0.0 {
+                Some(score.up as f64 * 100.0 / total)
+            } else {
+                None
+            }
+        }
+
+        impl Default for SortQuery {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+        impl SortQuery {
+            pub fn new() -> Self {
+                SortQuery { inputs: Vec::new() }
+            }
+
+            pub fn build<T>(&mut self, inputs: T)
+            where
+                T: Iterator<Item = SortInput>,
+            {
+                self.inputs.extend(inputs);
+            }
+
+            pub fn run(&self, results: &mut [GameQueryResult]) {
+                if self.inputs.is_empty() {
+                    return;
+                }
+                results.sort_by(|a, b| {
+                    for input in self.inputs.iter() {
+                        let (a, b) = (
+                            input.key.value(a, input.window),
+                            input.key.value(b, input.window),
+                        );
+                        let ordering = match (a, b) {
+                            (Some(a), Some(b)) => {
+                                let ordering = a.partial_cmp(&b).unwrap_or(Ordering::Equal);
+                                match input.order {
+                                    SortOrder::Asc => ordering,
+                                    SortOrder::Desc => ordering.reverse(),
+                                }
+                            }
+                            (Some(_), None) => Ordering::Less,
+                            (None, Some(_)) => Ordering::Greater,
+                            (None, None) => Ordering::Equal,
+                        };
+                        if ordering != Ordering::Equal {
+                            return ordering;
+                        }
+                    }
+                    Ordering::Equal
+                });
+            }
         }
     }
     pub mod id {
+        use super::explain::{record, Stage, StageCounts};
         use crate::entity::entity::game::Game;
-        use std::collections::HashSet;
+        use std::collections::BTreeSet;
 
-        use serde::Serialize;
+        use serde::{Deserialize, Serialize};
 
-        #[derive(Serialize)]
+        #[derive(Serialize, Deserialize, Clone)]
         pub enum QueryInput {
             GameId(IdFilterInput),
             TagId(IdFilterInput),
         }
 
-        #[derive(Serialize)]
+        #[derive(Serialize, Deserialize, Clone)]
         pub struct IdFilterInput {
             pub policy: FilterPolicy,
             pub list: Vec<u32>,
         }
 
-        #[derive(Serialize)]
+        #[derive(Serialize, Deserialize, Clone)]
         pub enum FilterPolicy {
             Include,
             Exclude,
         }
 
+        #[derive(Serialize, Clone)]
         pub struct IdFilter {
-            shouldIncludes: Option<HashSet<u32>>,
-            shouldExcludes: Option<HashSet<u32>>,
+            shouldIncludes: Option<BTreeSet<u32>>,
+            shouldExcludes: Option<BTreeSet<u32>>,
         }
 
+        #[derive(Serialize, Clone)]
         pub struct IdQuery {
             gameId: IdFilter,
             tagId: IdFilter,
         }
 
+        impl Default for IdFilter {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
         impl IdFilter {
             pub fn new() -> Self {
                 IdFilter {
@@ -65,10 +342,10 @@ pub mod query {
             pub fn add_input(&mut self, input: IdFilterInput) {
                 match input.policy {
                     FilterPolicy::Include if self.shouldIncludes.is_none() => {
-                        self.shouldIncludes = Some(HashSet::<u32>::new());
+                        self.shouldIncludes = Some(BTreeSet::<u32>::new());
                     }
                     FilterPolicy::Exclude if self.shouldExcludes.is_none() => {
-                        self.shouldExcludes = Some(HashSet::<u32>::new());
+                        self.shouldExcludes = Some(BTreeSet::<u32>::new());
                     }
                     _ => (),
                 }
@@ -80,7 +357,7 @@ pub mod query {
             }
 
             fn verify_single(&self, compareee: &u32) -> bool {
-                for (shouldHave, option) in vec![
+                for (shouldHave, option) in [
                     (true, self.shouldIncludes.as_ref()),
                     (false, self.shouldExcludes.as_ref()),
                 ] {
@@ -93,12 +370,12 @@ pub mod query {
                 true
             }
 
-            fn verify_list(&self, compareees: &Vec<u32>) -> bool {
+            fn verify_list(&self, compareees: &[u32]) -> bool {
                 if self.is_none() {
                     return true;
                 }
-                let compareees: HashSet<u32> = compareees.into_iter().cloned().collect();
-                for (shouldHave, option) in vec![
+                let compareees: BTreeSet<u32> = compareees.iter().cloned().collect();
+                for (shouldHave, option) in [
                     (true, self.shouldIncludes.as_ref()),
                     (false, self.shouldExcludes.as_ref()),
                 ] {
@@ -113,6 +390,11 @@ pub mod query {
             }
         }
 
+        impl Default for IdQuery {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
         impl IdQuery {
             pub fn new() -> Self {
                 IdQuery {
@@ -140,57 +422,313 @@ pub mod query {
             }
 
             pub fn run(&self, game: &Game) -> bool {
-                return self.gameId.verify_single(&game.id)
-                    && game
+                self.run_counted(game, &mut None)
+            }
+
+            pub fn run_counted(&self, game: &Game, counts: &mut Option<&mut StageCounts>) -> bool {
+                if !self.gameId.is_none()
+                    && !record(
+                        counts,
+                        None,
+                        Stage::GameId,
+                        self.gameId.verify_single(&game.id),
+                    )
+                {
+                    return false;
+                }
+                if !self.tagId.is_none() {
+                    let passed = game
                         .tags
                         .as_ref()
-                        .and_then(|tags| Some(self.tagId.verify_list(tags)))
+                        .map(|tags| self.tagId.verify_list(tags))
                         .unwrap_or(true);
+                    return record(counts, None, Stage::TagId, passed);
+                }
+                true
             }
         }
     }
 
     pub mod recommendation {
-        use crate::entity::entity::recommendation::{Recommendation, RecommendationScore};
-        use num_integer::Integer;
+        use super::explain::{record, Stage, StageCounts};
+        use crate::entity::entity::recommendation::{
+            wilson_lower_bound, Recommendation, RecommendationScore, TrendScore, WeightedScore,
+        };
+        use crate::error::{DatabaseError, DatabaseResult};
         use num_traits::ToPrimitive;
-        use serde::Serialize;
+        use serde::{Deserialize, Serialize};
+        use std::cmp::Ordering;
+        use std::collections::BTreeMap;
 
-        #[derive(Serialize)]
+        #[derive(Serialize, Deserialize, Clone)]
         pub enum QueryInput {
             Date(SimpleRangeInput),
             Total(SimpleRangeInput),
             Up(ComplexRangeInput),
             Down(ComplexRangeInput),
             Sum(ComplexRangeInput),
+            Decay(DecayInput),
+            Trend(TrendInput),
+            TrendPct(SimpleRangeInput),
+            TrendRate(SimpleRangeInput),
+            SinceRelease(SimpleRangeInput),
         }
+        #[derive(Serialize, Clone)]
         pub struct SimpleRange(Option<SimpleRangeInput>);
-        #[derive(Serialize)]
+        #[derive(Serialize, Deserialize, Clone)]
         pub struct SimpleRangeInput {
-            pub min: i32,
-            pub max: i32,
+            #[serde(default)]
+            pub min: Bound,
+            #[serde(default)]
+            pub max: Bound,
+        }
+        #[derive(Serialize, Deserialize, Clone, Default)]
+        pub enum Bound {
+            #[default]
+            Unbounded,
+            Inclusive(i64),
+            Exclusive(i64),
         }
+        #[derive(Serialize, Clone)]
         pub struct ComplexRange(Option<ComplexRangeInput>);
-        #[derive(Serialize)]
+        #[derive(Serialize, Deserialize, Clone)]
         pub struct ComplexRangeInput {
             pub format: RangeFormat,
             pub range: SimpleRangeInput,
         }
-        #[derive(Serialize)]
+        /// How a count is turned into the value the range is checked against. Percentages
+        /// are compared exactly, so bounds are fixed-point in units of `1 / baseline`.
+        #[derive(Serialize, Deserialize, Clone)]
         pub enum RangeFormat {
-            Pct { baseline: u32 },
+            Pct {
+                baseline: u32,
+            },
             Count,
+            /// Lower bound of the Wilson score interval at `z`, fixed-point like `Pct`: with
+            /// a baseline of 1000, 925 stands for 92.5%.
+            Wilson {
+                z: f64,
+                #[serde(default = "percent")]
+                baseline: u32,
+            },
+        }
+        fn percent() -> u32 {
+            100
+        }
+        #[derive(Serialize, Clone)]
+        pub struct Decay(Option<DecayInput>);
+        /// Exponential decay of review weight by age. When it is set, the `Total`, `Up`,
+        /// `Down` and `Sum` thresholds are checked against the weighted counts as they are,
+        /// without rounding them, and `RecommendationScore.wilson` comes from them as well.
+        #[derive(Serialize, Deserialize, Clone)]
+        pub struct DecayInput {
+            pub halfLifeDays: u32,
+            /// Reviews dated at or after this timestamp have full weight.
+            pub anchor: u32,
+        }
+
+        #[derive(Serialize, Clone)]
+        pub struct Trend(Option<TrendInput>);
+        /// Compares the last `days` days before `now` with the `days` days before that.
+        /// `TrendPct` then filters on the change of the up-percentage in points and
+        /// `TrendRate` on the change of reviews per day in percent; both are ignored
+        /// unless a `Trend` is given.
+        #[derive(Serialize, Deserialize, Clone)]
+        pub struct TrendInput {
+            pub days: u32,
+            pub now: u32,
         }
 
+        /// A review count or a value derived from it did not fit in 64 bits.
+        #[derive(Debug)]
+        pub struct Overflow;
+
+        const SECONDS_PER_DAY: f64 = 86400.0;
+        /// Used for `RecommendationScore.wilson` unless a `Wilson` range sets another `z`.
+        const DEFAULT_WILSON_Z: f64 = 1.96;
+
+        /// One input of a date window. Windows are told apart by their numeric `window` id,
+        /// which is also what results, sorting and export columns refer to; `name`, e.g.
+        /// "launch month", only labels the window in export headers and needs to be given
+        /// on one of its inputs.
+        #[derive(Serialize, Deserialize, Clone)]
+        pub struct WindowedInput {
+            pub window: u32,
+            #[serde(default, skip_serializing_if = "Option::is_none")]
+            pub name: Option<String>,
+            pub input: QueryInput,
+        }
+
+        /// A set of date windows, each with its own `RecommendationQuery`. A game has to
+        /// pass every window and gets one score per window.
+        #[derive(Serialize, Clone)]
+        pub struct WindowedQuery {
+            windows: BTreeMap<u32, RecommendationQuery>,
+            names: BTreeMap<u32, String>,
+        }
+
+        impl Default for WindowedQuery {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+        impl WindowedQuery {
+            pub fn new() -> Self {
+                WindowedQuery {
+                    windows: BTreeMap::new(),
+                    names: BTreeMap::new(),
+                }
+            }
+
+            /// The first input of each kind in a window wins. Fails, leaving the query as it
+            /// was, on inputs that `QueryInput::validate` rejects or that `validate` rejects
+            /// in combination.
+            pub fn build<T>(&mut self, inputs: T) -> DatabaseResult<()>
+            where
+                T: Iterator<Item = WindowedInput>,
+            {
+                self.apply(inputs, false)
+            }
+
+            /// Like `build`, but each input replaces whatever the same kind of input in the
+            /// same window was set to before, instead of being ignored.
+            pub fn bind<T>(&mut self, inputs: T) -> DatabaseResult<()>
+            where
+                T: Iterator<Item = WindowedInput>,
+            {
+                self.apply(inputs, true)
+            }
+
+            fn apply<T>(&mut self, inputs: T, replace: bool) -> DatabaseResult<()>
+            where
+                T: Iterator<Item = WindowedInput>,
+            {
+                let mut next = self.clone();
+                for WindowedInput {
+                    window,
+                    name,
+                    input,
+                } in inputs
+                {
+                    input.validate()?;
+                    if let Some(name) = name {
+                        if replace || !next.names.contains_key(&window) {
+                            next.names.insert(window, name);
+                        }
+                    }
+                    next.windows
+                        .entry(window)
+                        .or_default()
+                        .commit(input, replace);
+                }
+                next.validate()?;
+                *self = next;
+                Ok(())
+            }
+
+            /// Rejects windows whose inputs only make sense together with another input that
+            /// is missing, e.g. `TrendPct` without a `Trend`, which could never be checked,
+            /// and names that are given to more than one window.
+            fn validate(&self) -> DatabaseResult<()> {
+                let mut named = BTreeMap::new();
+                for (window, name) in self.names.iter() {
+                    if let Some(other) = named.insert(name, window) {
+                        return Err(DatabaseError::invalid_input(format!(
+                            "windows {} and {} are both named {:?}",
+                            other, window, name
+                        )));
+                    }
+                }
+                for (window, query) in self.windows.iter() {
+                    let trendless = query.trend.is_none()
+                        && !(query.trendPct.is_none() && query.trendRate.is_none());
+                    if trendless {
+                        return Err(DatabaseError::invalid_input(format!(
+                            "window {} filters on the trend but has no Trend input",
+                            window
+                        )));
+                    }
+                }
+                Ok(())
+            }
+
+            /// The names given to windows, by window id.
+            pub fn names(&self) -> &BTreeMap<u32, String> {
+                &self.names
+            }
+
+            /// Whether any window can reject a game, as opposed to only shaping its scores.
+            pub fn has_predicates(&self) -> bool {
+                self.windows
+                    .values()
+                    .any(RecommendationQuery::has_predicates)
+            }
+
+            /// Without any input, window `0` covers all recommendations unconditionally.
+            pub fn run(
+                &self,
+                recommendations: &[Recommendation],
+                releaseDate: Option<u32>,
+            ) -> Result<Option<BTreeMap<u32, RecommendationScore>>, Overflow> {
+                self.run_counted(recommendations, releaseDate, &mut None)
+            }
+
+            pub fn run_counted(
+                &self,
+                recommendations: &[Recommendation],
+                releaseDate: Option<u32>,
+                counts: &mut Option<&mut StageCounts>,
+            ) -> Result<Option<BTreeMap<u32, RecommendationScore>>, Overflow> {
+                let mut scores = BTreeMap::new();
+                if self.windows.is_empty() {
+                    let query = RecommendationQuery::new();
+                    if let Some(score) = query.run(recommendations, releaseDate)? {
+                        scores.insert(0, score);
+                    }
+                    return Ok(Some(scores));
+                }
+                for (window, query) in self.windows.iter() {
+                    match query.run_counted(recommendations, releaseDate, *window, counts)? {
+                        Some(score) => scores.insert(*window, score),
+                        None => return Ok(None),
+                    };
+                }
+                Ok(Some(scores))
+            }
+        }
+
+        #[derive(Serialize, Clone)]
         pub struct RecommendationQuery {
             date: SimpleRange,
             total: SimpleRange,
             up: ComplexRange,
             down: ComplexRange,
             sum: ComplexRange,
+            decay: Decay,
+            trend: Trend,
+            trendPct: SimpleRange,
+            trendRate: SimpleRange,
+            /// Like `date`, but in days since the release of each game.
+            sinceRelease: SimpleRange,
         }
 
+        impl Default for RecommendationQuery {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
         impl RecommendationQuery {
+            fn has_predicates(&self) -> bool {
+                !(self.date.is_none()
+                    && self.total.is_none()
+                    && self.up.is_none()
+                    && self.down.is_none()
+                    && self.sum.is_none()
+                    && self.trendPct.is_none()
+                    && self.trendRate.is_none()
+                    && self.sinceRelease.is_none())
+            }
+
             pub fn new() -> Self {
                 RecommendationQuery {
                     date: SimpleRange::None(),
@@ -198,76 +736,233 @@ pub mod query {
                     up: ComplexRange::None(),
                     down: ComplexRange::None(),
                     sum: ComplexRange::None(),
+                    decay: Decay::None(),
+                    trend: Trend::None(),
+                    trendPct: SimpleRange::None(),
+                    trendRate: SimpleRange::None(),
+                    sinceRelease: SimpleRange::None(),
                 }
             }
 
-            pub fn build<T>(&mut self, inputs: T)
-            where
-                T: Iterator<Item = QueryInput>,
-            {
-                inputs.for_each(|input| {
-                    macro_rules! commit_query {
-                        (
-                            $(($Field:ident, $field:ident, $kind:ident)),*,
-                        ) => (
-                            match input {
-                                $(QueryInput::$Field(filter) => {
-                                    if self.$field.is_none() {
-                                        self.$field = $kind(Some(filter));
-                                    }
-                                }),*
-                            }
-                        )
-                    };
-                    commit_query!(
-                        (Date, date, SimpleRange),
-                        (Total, total, SimpleRange),
-                        (Up, up, ComplexRange),
-                        (Down, down, ComplexRange),
-                        (Sum, sum, ComplexRange),
-                    );
-                });
+            fn commit(&mut self, input: QueryInput, replace: bool) {
+                macro_rules! commit_query {
+                    (
+                        $(($Field:ident, $field:ident, $kind:ident)),*,
+                    ) => (
+                        match input {
+                            $(QueryInput::$Field(filter) => {
+                                if replace || self.$field.is_none() {
+                                    self.$field = $kind(Some(filter));
+                                }
+                            }),*
+                        }
+                    )
+                }
+                commit_query!(
+                    (Date, date, SimpleRange),
+                    (Total, total, SimpleRange),
+                    (Up, up, ComplexRange),
+                    (Down, down, ComplexRange),
+                    (Sum, sum, ComplexRange),
+                    (Decay, decay, Decay),
+                    (Trend, trend, Trend),
+                    (TrendPct, trendPct, SimpleRange),
+                    (TrendRate, trendRate, SimpleRange),
+                    (SinceRelease, sinceRelease, SimpleRange),
+                );
             }
 
             pub fn run(
                 &self,
-                recommendations: &Vec<Recommendation>,
-            ) -> Option<RecommendationScore> {
-                if self.date.is_none() {
-                    self.evaluate(recommendations.iter())
+                recommendations: &[Recommendation],
+                releaseDate: Option<u32>,
+            ) -> Result<Option<RecommendationScore>, Overflow> {
+                self.run_counted(recommendations, releaseDate, 0, &mut None)
+            }
+
+            /// `window` only labels the stages counted in `counts`.
+            pub fn run_counted(
+                &self,
+                recommendations: &[Recommendation],
+                releaseDate: Option<u32>,
+                window: u32,
+                counts: &mut Option<&mut StageCounts>,
+            ) -> Result<Option<RecommendationScore>, Overflow> {
+                let window = Some(window);
+                let trend = match self.trend.0.as_ref() {
+                    None => None,
+                    Some(trend) => {
+                        let trend = trend.evaluate(recommendations);
+                        for (stage, range, v) in [
+                            (Stage::TrendPct, &self.trendPct, trend.pctChange),
+                            (Stage::TrendRate, &self.trendRate, trend.rateChange),
+                        ] {
+                            let passed = v.is_some_and(|v| range.verify_f64(v));
+                            if !range.is_none() && !record(counts, window, stage, passed) {
+                                return Ok(None);
+                            }
+                        }
+                        Some(trend)
+                    }
+                };
+                let mut score = if self.date.is_none() && self.sinceRelease.is_none() {
+                    self.evaluate(recommendations.iter(), window, counts)
                 } else {
-                    self.evaluate(
-                        recommendations
-                            .iter()
-                            .filter(|r| self.date.verify_u32(r.date)),
-                    )
+                    let releaseDate = match (self.sinceRelease.0.as_ref(), releaseDate) {
+                        (None, _) => 0,
+                        (Some(_), releaseDate) => {
+                            let passed = releaseDate.is_some();
+                            if !record(counts, window, Stage::ReleaseDate, passed) {
+                                return Ok(None);
+                            }
+                            releaseDate.unwrap_or(0) as i64
+                        }
+                    };
+                    let (mut examined, mut rejected) = (0, 0);
+                    let buckets = recommendations.iter().filter(|r| {
+                        let passed = self.date.verify(r.date as i64)
+                            && self.sinceRelease.verify_ratio(
+                                (r.date as i64 - releaseDate) as i128,
+                                SECONDS_PER_DAY as i128,
+                            );
+                        examined += 1;
+                        rejected += if passed { 0 } else { 1 };
+                        passed
+                    });
+                    let score = self.evaluate(buckets, window, counts);
+                    if let Some(counts) = counts {
+                        counts.add(window, Stage::DateBucket, examined, rejected);
+                    }
+                    score
+                }?;
+                if let Some(score) = score.as_mut() {
+                    score.trend = trend;
                 }
+                Ok(score)
             }
 
-            fn evaluate<'a, T>(&self, iter: T) -> Option<RecommendationScore>
+            fn evaluate<'a, T>(
+                &self,
+                iter: T,
+                window: Option<u32>,
+                counts: &mut Option<&mut StageCounts>,
+            ) -> Result<Option<RecommendationScore>, Overflow>
             where
                 T: Iterator<Item = &'a Recommendation>,
             {
-                let mut up = 0;
-                let mut down = 0;
-                iter.for_each(|r| {
-                    up += r.up;
-                    down += r.down;
-                });
-                let total = up + down;
-                let sum = if let (Some(up), Some(down)) = (up.to_i32(), down.to_i32()) {
-                    up - down
-                } else {
-                    return None;
+                let mut up: u64 = 0;
+                let mut down: u64 = 0;
+                let mut weightedUp = 0.0;
+                let mut weightedDown = 0.0;
+                for r in iter {
+                    up = up.checked_add(r.up as u64).ok_or(Overflow)?;
+                    down = down.checked_add(r.down as u64).ok_or(Overflow)?;
+                    if let Some(decay) = self.decay.0.as_ref() {
+                        let weight = decay.weight(r.date);
+                        weightedUp += r.up as f64 * weight;
+                        weightedDown += r.down as f64 * weight;
+                    }
+                }
+                let weighted = self
+                    .decay
+                    .0
+                    .as_ref()
+                    .map(|_| WeightedScore::new(weightedUp, weightedDown));
+                let sum = match (up.to_i64(), down.to_i64()) {
+                    (Some(up), Some(down)) => up - down,
+                    _ => return Err(Overflow),
                 };
-                if self.total.verify_u32(total)
-                    && self.up.verify_u32(up, total)
-                    && self.down.verify_u32(down, total)
-                    && self.sum.verify_i32(sum, total)
-                {
-                    Some(RecommendationScore { up, down, sum })
-                } else {
-                    None
+
+                let [totalPassed, upPassed, downPassed, sumPassed] = match weighted.as_ref() {
+                    None => {
+                        let total = up.checked_add(down).ok_or(Overflow)?;
+                        let (iUp, iDown, iTotal) =
+                            match (up.to_i64(), down.to_i64(), total.to_i64()) {
+                                (Some(iUp), Some(iDown), Some(iTotal)) => (iUp, iDown, iTotal),
+                                _ => return Err(Overflow),
+                            };
+                        [
+                            self.total.verify(iTotal),
+                            self.up.verify(iUp, iTotal),
+                            self.down.verify(iDown, iTotal),
+                            self.sum.verify(iUp - iDown, iTotal),
+                        ]
+                    }
+                    Some(weighted) => {
+                        let total = weighted.up + weighted.down;
+                        [
+                            self.total.verify_f64(total),
+                            self.up.verify_f64(weighted.up, total),
+                            self.down.verify_f64(weighted.down, total),
+                            self.sum.verify_f64(weighted.sum, total),
+                        ]
+                    }
+                };
+                let (wUp, wDown) = match weighted.as_ref() {
+                    None => (up as f64, down as f64),
+                    Some(weighted) => (weighted.up, weighted.down),
+                };
+                let wilson = wilson_lower_bound(wUp, wUp + wDown, self.wilson_z());
+                for (stage, active, passed) in [
+                    (Stage::Total, !self.total.is_none(), totalPassed),
+                    (Stage::Up, !self.up.is_none(), upPassed),
+                    (Stage::Down, !self.down.is_none(), downPassed),
+                    (Stage::Sum, !self.sum.is_none(), sumPassed),
+                ] {
+                    if active && !record(counts, window, stage, passed) {
+                        return Ok(None);
+                    }
+                }
+                Ok(Some(RecommendationScore {
+                    up,
+                    down,
+                    sum,
+                    wilson,
+                    weighted,
+                    trend: None,
+                }))
+            }
+        }
+
+        impl QueryInput {
+            /// Rejects inputs that decode fine but cannot be evaluated.
+            pub fn validate(&self) -> DatabaseResult<()> {
+                match self {
+                    QueryInput::Decay(DecayInput {
+                        halfLifeDays: 0, ..
+                    }) => Err(DatabaseError::invalid_input("half-life must not be 0")),
+                    QueryInput::Trend(TrendInput { days: 0, .. }) => {
+                        Err(DatabaseError::invalid_input("trend window must not be 0"))
+                    }
+                    QueryInput::Sum(ComplexRangeInput {
+                        format: RangeFormat::Wilson { .. },
+                        ..
+                    }) => Err(DatabaseError::invalid_input(
+                        "Sum cannot be filtered by Wilson score",
+                    )),
+                    QueryInput::Up(ComplexRangeInput {
+                        format: RangeFormat::Wilson { z, baseline },
+                        ..
+                    })
+                    | QueryInput::Down(ComplexRangeInput {
+                        format: RangeFormat::Wilson { z, baseline },
+                        ..
+                    }) => {
+                        // NaN fails this as well
+                        if !(*z > 0.0 && z.is_finite()) {
+                            Err(DatabaseError::invalid_input(format!(
+                                "Wilson z must be positive, not {}",
+                                z
+                            )))
+                        } else if *baseline == 0 {
+                            Err(DatabaseError::invalid_input(
+                                "Wilson baseline must not be 0",
+                            ))
+                        } else {
+                            Ok(())
+                        }
+                    }
+                    _ => Ok(()),
                 }
             }
         }
@@ -279,21 +974,110 @@ pub mod query {
             pub fn is_none(&self) -> bool {
                 self.0.is_none()
             }
-            pub fn verify_u32(&self, v: u32) -> bool {
-                self.0.is_none() || self.0.as_ref().unwrap().verify_u32(v)
+            pub fn verify(&self, v: i64) -> bool {
+                self.0.is_none() || self.0.as_ref().unwrap().verify(v)
+            }
+            pub fn verify_f64(&self, v: f64) -> bool {
+                self.0.is_none() || self.0.as_ref().unwrap().verify_f64(v)
+            }
+            pub fn verify_ratio(&self, numerator: i128, denominator: i128) -> bool {
+                self.0.is_none()
+                    || self
+                        .0
+                        .as_ref()
+                        .unwrap()
+                        .verify_ratio(numerator, denominator)
             }
-            // pub fn verify_i32(&self, v: i32) -> bool {
-            //     self.0.is_none() || self.0.as_ref().unwrap().verify_i32(v)
-            // }
         }
 
         impl SimpleRangeInput {
-            fn verify_u32(&self, v: u32) -> bool {
-                return (self.min.is_negative() || v >= self.min.to_u32().unwrap())
-                    && (self.max.is_positive() || v <= self.max.to_u32().unwrap());
+            fn verify(&self, v: i64) -> bool {
+                self.verify_ratio(v as i128, 1)
+            }
+            /// Checks `numerator / denominator` without rounding; `denominator` must be positive.
+            fn verify_ratio(&self, numerator: i128, denominator: i128) -> bool {
+                let compare = |bound: i64| numerator.cmp(&(bound as i128 * denominator));
+                self.min.admits(compare, Ordering::Greater)
+                    && self.max.admits(compare, Ordering::Less)
+            }
+            fn verify_f64(&self, v: f64) -> bool {
+                let compare = |bound: i64| v.partial_cmp(&(bound as f64)).unwrap_or(Ordering::Less);
+                self.min.admits(compare, Ordering::Greater)
+                    && self.max.admits(compare, Ordering::Less)
+            }
+        }
+
+        impl Bound {
+            /// `compare` orders the value against a bound; `inside` is the ordering a value
+            /// strictly within the range has against this bound.
+            fn admits<T>(&self, compare: T, inside: Ordering) -> bool
+            where
+                T: Fn(i64) -> Ordering,
+            {
+                match *self {
+                    Bound::Unbounded => true,
+                    Bound::Inclusive(bound) => compare(bound) != inside.reverse(),
+                    Bound::Exclusive(bound) => compare(bound) == inside,
+                }
+            }
+        }
+
+        impl RecommendationQuery {
+            fn wilson_z(&self) -> f64 {
+                [&self.up, &self.down]
+                    .iter()
+                    .filter_map(|range| match range.0.as_ref()?.format {
+                        RangeFormat::Wilson { z, .. } => Some(z),
+                        _ => None,
+                    })
+                    .next()
+                    .unwrap_or(DEFAULT_WILSON_Z)
+            }
+        }
+
+        impl Decay {
+            pub fn None() -> Self {
+                Decay(None)
+            }
+            pub fn is_none(&self) -> bool {
+                self.0.is_none()
+            }
+        }
+
+        impl DecayInput {
+            fn weight(&self, date: u32) -> f64 {
+                let age = self.anchor.saturating_sub(date) as f64 / SECONDS_PER_DAY;
+                0.5f64.powf(age / self.halfLifeDays as f64)
             }
-            fn verify_i32(&self, v: i32) -> bool {
-                v >= self.min && v <= self.max
+        }
+
+        impl Trend {
+            pub fn None() -> Self {
+                Trend(None)
+            }
+            pub fn is_none(&self) -> bool {
+                self.0.is_none()
+            }
+        }
+
+        impl TrendInput {
+            fn evaluate(&self, recommendations: &[Recommendation]) -> TrendScore {
+                let span = self.days.saturating_mul(SECONDS_PER_DAY as u32);
+                let recentStart = self.now.saturating_sub(span);
+                let previousStart = recentStart.saturating_sub(span);
+                let (mut recent, mut previous) = ((0, 0), (0, 0));
+                for r in recommendations.iter() {
+                    let window = if r.date > recentStart && r.date <= self.now {
+                        &mut recent
+                    } else if r.date > previousStart && r.date <= recentStart {
+                        &mut previous
+                    } else {
+                        continue;
+                    };
+                    window.0 += r.up as u64;
+                    window.1 += r.down as u64;
+                }
+                TrendScore::new(self.days, recent, previous)
             }
         }
 
@@ -304,36 +1088,131 @@ pub mod query {
             pub fn is_none(&self) -> bool {
                 self.0.is_none()
             }
-            pub fn verify_u32(&self, v: u32, total: u32) -> bool {
-                self.0.is_none() || self.0.as_ref().unwrap().verify_u32(v, total)
+            pub fn verify(&self, v: i64, total: i64) -> bool {
+                self.0.is_none() || self.0.as_ref().unwrap().verify(v, total)
             }
-            pub fn verify_i32(&self, v: i32, total: u32) -> bool {
-                self.0.is_none() || self.0.as_ref().unwrap().verify_i32(v, total)
+            pub fn verify_f64(&self, v: f64, total: f64) -> bool {
+                self.0.is_none() || self.0.as_ref().unwrap().verify_f64(v, total)
             }
         }
 
         impl ComplexRangeInput {
-            fn verify_u32(&self, v: u32, total: u32) -> bool {
-                let compareee = match self.format {
-                    RangeFormat::Count => v,
-                    RangeFormat::Pct { baseline } if total != 0 => (v * baseline).div_ceil(&total),
-                    _ => 0,
-                };
-                self.range.verify_u32(compareee)
-            }
-            fn verify_i32(&self, v: i32, total: u32) -> bool {
-                let compareee = match self.format {
-                    RangeFormat::Count => v,
-                    RangeFormat::Pct { baseline } if total != 0 => {
-                        if let (Some(total), Some(baseline)) = (total.to_i32(), baseline.to_i32()) {
-                            (v * baseline).div_ceil(&total)
-                        } else {
-                            return false;
-                        }
+            fn verify(&self, v: i64, total: i64) -> bool {
+                match self.format {
+                    RangeFormat::Count => self.range.verify(v),
+                    RangeFormat::Pct { baseline } if total > 0 => self
+                        .range
+                        .verify_ratio(v as i128 * baseline as i128, total as i128),
+                    RangeFormat::Wilson { z, baseline } => self.range.verify_f64(
+                        wilson_lower_bound(v as f64, total as f64, z) * baseline as f64,
+                    ),
+                    _ => self.range.verify(0),
+                }
+            }
+            /// Like `verify` for weighted counts, which are not whole numbers.
+            fn verify_f64(&self, v: f64, total: f64) -> bool {
+                match self.format {
+                    RangeFormat::Count => self.range.verify_f64(v),
+                    RangeFormat::Pct { baseline } if total > 0.0 => {
+                        self.range.verify_f64(v * baseline as f64 / total)
                     }
-                    _ => 0,
+                    RangeFormat::Wilson { z, baseline } => self
+                        .range
+                        .verify_f64(wilson_lower_bound(v, total, z) * baseline as f64),
+                    _ => self.range.verify(0),
+                }
+            }
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+
+            fn range(min: Bound, max: Bound) -> SimpleRangeInput {
+                SimpleRangeInput { min, max }
+            }
+
+            fn pct(min: Bound, max: Bound) -> ComplexRangeInput {
+                ComplexRangeInput {
+                    format: RangeFormat::Pct { baseline: 100 },
+                    range: range(min, max),
+                }
+            }
+
+            #[test]
+            fn inclusive_bounds_admit_their_value() {
+                let r = range(Bound::Inclusive(10), Bound::Inclusive(20));
+                assert!(!r.verify(9));
+                assert!(r.verify(10));
+                assert!(r.verify(20));
+                assert!(!r.verify(21));
+            }
+
+            #[test]
+            fn exclusive_bounds_reject_their_value() {
+                let r = range(Bound::Exclusive(10), Bound::Exclusive(20));
+                assert!(!r.verify(10));
+                assert!(r.verify(11));
+                assert!(r.verify(19));
+                assert!(!r.verify(20));
+            }
+
+            #[test]
+            fn unbounded_sides_admit_everything() {
+                assert!(range(Bound::Unbounded, Bound::Unbounded).verify(i64::MIN));
+                assert!(range(Bound::Unbounded, Bound::Unbounded).verify(i64::MAX));
+                let atMost = range(Bound::Unbounded, Bound::Inclusive(0));
+                assert!(atMost.verify(i64::MIN));
+                assert!(!atMost.verify(1));
+                let atLeast = range(Bound::Inclusive(0), Bound::Unbounded);
+                assert!(atLeast.verify(i64::MAX));
+                assert!(!atLeast.verify(-1));
+            }
+
+            #[test]
+            fn ratios_are_compared_without_rounding() {
+                // 949/1000 is 94.9%, which `div_ceil` used to round up to 95
+                let atLeast95 = pct(Bound::Inclusive(95), Bound::Unbounded);
+                assert!(!atLeast95.verify(949, 1000));
+                assert!(atLeast95.verify(950, 1000));
+                let below95 = pct(Bound::Unbounded, Bound::Exclusive(95));
+                assert!(below95.verify(949, 1000));
+                assert!(!below95.verify(950, 1000));
+            }
+
+            #[test]
+            fn negative_sums_compare_below_zero() {
+                let sum = ComplexRangeInput {
+                    format: RangeFormat::Count,
+                    range: range(Bound::Inclusive(-10), Bound::Exclusive(0)),
                 };
-                self.range.verify_i32(compareee)
+                assert!(sum.verify(-10, 30));
+                assert!(sum.verify(-1, 30));
+                assert!(!sum.verify(-11, 30));
+                assert!(!sum.verify(0, 30));
+                let negativePct = pct(Bound::Inclusive(-50), Bound::Inclusive(-50));
+                assert!(negativePct.verify(-5, 10));
+                assert!(!negativePct.verify(-4, 10));
+            }
+
+            #[test]
+            fn percentages_of_nothing_are_zero() {
+                assert!(pct(Bound::Unbounded, Bound::Inclusive(0)).verify(0, 0));
+                assert!(pct(Bound::Inclusive(0), Bound::Unbounded).verify(0, 0));
+                assert!(!pct(Bound::Exclusive(0), Bound::Unbounded).verify(0, 0));
+                assert!(!pct(Bound::Inclusive(50), Bound::Unbounded).verify(0, 0));
+            }
+
+            #[test]
+            fn fractions_are_compared_exactly() {
+                let r = range(Bound::Exclusive(1), Bound::Inclusive(2));
+                assert!(!r.verify_ratio(3, 3));
+                assert!(r.verify_ratio(4, 3));
+                assert!(r.verify_ratio(6, 3));
+                assert!(!r.verify_ratio(7, 3));
+                assert!(r.verify_f64(1.5));
+                assert!(!r.verify_f64(1.0));
+                assert!(!r.verify_f64(f64::NAN));
             }
         }
     }
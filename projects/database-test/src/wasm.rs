@@ -0,0 +1,593 @@
+use crate::database::{Database, User};
+use crate::entity::entity::game::GameInput;
+use crate::error::{DatabaseError, DatabaseResult, ErrorCode};
+use crate::export::ExportOptions;
+use crate::import::csv::{parse_catalog, CsvMapping};
+use crate::import::json::parse_games;
+use crate::import::ndjson::NdjsonIngest;
+use crate::import::steam::parse_histogram;
+use crate::query::query::game::GameQuery;
+use crate::query::query::id::QueryInput as IdQueryInput;
+use crate::query::query::recommendation::WindowedInput as RecommendationQueryInput;
+use crate::query::query::sort::SortInput;
+use crate::wasm_utils::js_value;
+use crate::wasm_utils::{LinerJavaScriptInput, LinerJavaScriptOutput};
+
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Every open database, prepared query and ingest by handle. They share one sequence of handles
+/// that starts at 1 and is never reused, so 0 can stand for "nothing" on the JS side and a
+/// stale handle never reaches someone else's data.
+///
+/// JS can call back into an export while another one is still running, e.g. from the
+/// `log` or `resolve` imports. Each entry therefore sits in its own `RefCell`: reads
+/// nest with reads, and anything that would overlap with a write fails with a busy error
+/// instead of aliasing the entry. The registry itself is only borrowed for lookups.
+struct Registry {
+    databases: HashMap<u32, Rc<RefCell<Database>>>,
+    queries: HashMap<u32, Rc<RefCell<GameQuery>>>,
+    ingests: HashMap<u32, Rc<RefCell<Ingest>>>,
+    nextHandle: u32,
+}
+
+/// An NDJSON ingest into the database at `db`, see `beginIngest`.
+struct Ingest {
+    db: u32,
+    reader: NdjsonIngest,
+}
+impl Registry {
+    fn next_handle(&mut self) -> DatabaseResult<u32> {
+        let handle = self.nextHandle;
+        self.nextHandle = handle
+            .checked_add(1)
+            .ok_or_else(|| DatabaseError::new(ErrorCode::Overflow, "ran out of handles"))?;
+        Ok(handle)
+    }
+}
+
+static mut REGISTRY_PTR: Option<*const RefCell<Registry>> = None;
+fn registry() -> &'static RefCell<Registry> {
+    let pointer = match unsafe { REGISTRY_PTR } {
+        Some(pointer) => pointer,
+        None => {
+            let pointer = Box::into_raw(Box::new(RefCell::new(Registry {
+                databases: HashMap::new(),
+                queries: HashMap::new(),
+                ingests: HashMap::new(),
+                nextHandle: 1,
+            })));
+            unsafe {
+                REGISTRY_PTR = Some(pointer);
+            }
+            pointer
+        }
+    };
+    unsafe { &*pointer }
+}
+
+fn open_db(mut db: Database) -> DatabaseResult<u32> {
+    db.set_clock(host_clock);
+    let mut registry = registry().borrow_mut();
+    let handle = registry.next_handle()?;
+    registry.databases.insert(handle, Rc::new(RefCell::new(db)));
+    Ok(handle)
+}
+
+fn open_query(query: GameQuery) -> DatabaseResult<u32> {
+    let mut registry = registry().borrow_mut();
+    let handle = registry.next_handle()?;
+    registry
+        .queries
+        .insert(handle, Rc::new(RefCell::new(query)));
+    Ok(handle)
+}
+
+fn get_query(handle: u32) -> DatabaseResult<Rc<RefCell<GameQuery>>> {
+    registry()
+        .borrow()
+        .queries
+        .get(&handle)
+        .cloned()
+        .ok_or_else(|| DatabaseError::unknown_handle(handle))
+}
+
+fn get_ingest(handle: u32) -> DatabaseResult<Rc<RefCell<Ingest>>> {
+    registry()
+        .borrow()
+        .ingests
+        .get(&handle)
+        .cloned()
+        .ok_or_else(|| DatabaseError::unknown_handle(handle))
+}
+
+fn get_db(handle: u32) -> DatabaseResult<Rc<RefCell<Database>>> {
+    registry()
+        .borrow()
+        .databases
+        .get(&handle)
+        .cloned()
+        .ok_or_else(|| DatabaseError::unknown_handle(handle))
+}
+
+/// Runs `task` with exclusive access to the database at `handle`.
+fn with_db<T, R>(handle: u32, task: T) -> DatabaseResult<R>
+where
+    T: FnOnce(&mut Database) -> DatabaseResult<R>,
+{
+    let db = get_db(handle)?;
+    let mut db = db
+        .try_borrow_mut()
+        .map_err(|_| DatabaseError::busy(handle))?;
+    task(&mut db)
+}
+
+/// Runs `task` with shared access to the database at `handle`; only fails as busy while
+/// a write is in progress.
+fn read_db<T, R>(handle: u32, task: T) -> DatabaseResult<R>
+where
+    T: FnOnce(&Database) -> DatabaseResult<R>,
+{
+    let db = get_db(handle)?;
+    let db = db.try_borrow().map_err(|_| DatabaseError::busy(handle))?;
+    task(&db)
+}
+
+/// Hands a freshly opened handle to JS, or reports the error and hands back 0.
+fn hand_out(result: DatabaseResult<u32>) -> u32 {
+    match result {
+        Ok(handle) => handle,
+        Err(error) => {
+            error.write_js();
+            0
+        }
+    }
+}
+
+/// Hands the value of an export to JS, or the error if there is one.
+fn respond<T>(result: DatabaseResult<T>)
+where
+    T: LinerJavaScriptOutput,
+{
+    match result {
+        Ok(value) => value.write_js(),
+        Err(error) => error.write_js(),
+    }
+}
+
+/// Like `respond`, for exports that hand nothing back on success.
+fn report(result: DatabaseResult<()>) {
+    if let Err(error) = result {
+        error.write_js();
+    }
+}
+
+fn to_json<T>(value: &T) -> DatabaseResult<String>
+where
+    T: Serialize,
+{
+    serde_json::to_string(value).map_err(DatabaseError::serialize)
+}
+
+#[link(wasm_import_module = "ctx")]
+extern "C" {
+    /// The host clock in milliseconds, e.g. `performance.now`.
+    fn now() -> f64;
+}
+
+fn host_clock() -> f64 {
+    unsafe { now() }
+}
+
+#[link(wasm_import_module = "console")]
+extern "C" {
+    #[allow(dead_code)]
+    fn log(ptr: usize, len: usize);
+}
+#[allow(dead_code)]
+pub fn consoleLog(msg: &str) {
+    unsafe { log(msg.as_ptr() as usize, msg.len()) }
+}
+#[macro_export]
+macro_rules! consoleLog {
+    ($v:expr) => {
+        consoleLog(&serde_json::to_string($v).unwrap());
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn alloc(capacity: usize) -> js_value::Pointer {
+    js_value::memory::alloc(capacity)
+}
+
+#[no_mangle]
+pub extern "C" fn persist(handle: u32) {
+    respond(read_db(handle, |db| db.snapshot()));
+}
+
+#[no_mangle]
+pub extern "C" fn getFullJson(handle: u32) {
+    respond(read_db(handle, |db| db.to_json()));
+}
+
+/// Opens a database from the snapshot at `ptr`, or an empty one if it is empty, and
+/// returns its handle. Returns 0 if the snapshot could not be read.
+#[no_mangle]
+pub extern "C" fn open(ptr: js_value::Pointer) -> u32 {
+    hand_out(Vec::<u8>::read_from_js(ptr).and_then(|snapshot| {
+        let db = if snapshot.is_empty() {
+            Database::new()
+        } else {
+            Database::restore(&snapshot)?
+        };
+        open_db(db)
+    }))
+}
+
+/// Opens an independent copy of the database at `handle`, e.g. to preview an import
+/// without touching the original. Returns 0 if there is no such database. See
+/// `Database::fork` for what the copy starts with.
+#[no_mangle]
+pub extern "C" fn fork(handle: u32) -> u32 {
+    hand_out(read_db(handle, |db| Ok(db.fork())).and_then(open_db))
+}
+
+/// Responds with the `MetricsReport` of the database at `handle`, then resets its
+/// counters if `reset` is not 0, so that nothing is lost between two reads.
+#[no_mangle]
+pub extern "C" fn getMetrics(handle: u32, reset: u32) {
+    respond(if reset == 0 {
+        read_db(handle, |db| to_json(&db.metrics()))
+    } else {
+        with_db(handle, |db| {
+            let metrics = to_json(&db.metrics())?;
+            db.reset_metrics();
+            Ok(metrics)
+        })
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn close(handle: u32) {
+    report(get_db(handle).and_then(|db| {
+        if db.try_borrow_mut().is_err() {
+            return Err(DatabaseError::busy(handle));
+        }
+        registry().borrow_mut().databases.remove(&handle);
+        Ok(())
+    }));
+}
+
+/// Upserts every valid record of a `GameInput` array and responds with the problems of
+/// the others, see `import::json::parse_games`.
+#[no_mangle]
+pub extern "C" fn updateGames(handle: u32, ptr: js_value::Pointer) {
+    respond(update_games(handle, ptr));
+}
+fn update_games(handle: u32, ptr: js_value::Pointer) -> DatabaseResult<String> {
+    let json = String::read_from_js(ptr)?;
+    let batch = parse_games(&json)?;
+    with_db(handle, |db| {
+        db.upsert_games(batch.games)?;
+        to_json(&batch.errors)
+    })
+}
+
+/// Starts loading a catalog into the database at `handle` that is too large for
+/// `updateGames`, and returns the handle of the ingest. Returns 0 if there is no such
+/// database. Feed the catalog as newline-delimited `GameInput`s with `feedIngest`, then
+/// call `finishIngest`, or `abortIngest` to give up.
+///
+/// Games are upserted chunk by chunk, so an ingest that stops halfway leaves the games
+/// before it in place. Wrap it in `begin` and `commit` to make it all or nothing, at the
+/// cost of a copy of the database for the savepoint.
+#[no_mangle]
+pub extern "C" fn beginIngest(handle: u32) -> u32 {
+    hand_out(get_db(handle).and_then(|_| {
+        let mut registry = registry().borrow_mut();
+        let ingest = registry.next_handle()?;
+        let reader = NdjsonIngest::new();
+        let entry = Rc::new(RefCell::new(Ingest { db: handle, reader }));
+        registry.ingests.insert(ingest, entry);
+        Ok(ingest)
+    }))
+}
+
+/// Upserts the games of every line the chunk at `ptr` completes. A chunk may end anywhere,
+/// even inside a record or a UTF-8 sequence. Responds with the `IngestProgress` so far.
+#[no_mangle]
+pub extern "C" fn feedIngest(ingest: u32, ptr: js_value::Pointer) {
+    respond(
+        Vec::<u8>::read_from_js(ptr)
+            .and_then(|chunk| step_ingest(ingest, |reader| reader.feed(&chunk))),
+    );
+}
+
+/// Upserts the last line if it did not end with a line break, closes the ingest and
+/// responds with its final `IngestProgress`.
+#[no_mangle]
+pub extern "C" fn finishIngest(ingest: u32) {
+    respond(step_ingest(ingest, NdjsonIngest::finish).inspect(|_| {
+        registry().borrow_mut().ingests.remove(&ingest);
+    }));
+}
+
+fn step_ingest<T>(handle: u32, read: T) -> DatabaseResult<String>
+where
+    T: FnOnce(&mut NdjsonIngest) -> Vec<GameInput>,
+{
+    let ingest = get_ingest(handle)?;
+    let mut ingest = ingest
+        .try_borrow_mut()
+        .map_err(|_| DatabaseError::busy(handle))?;
+    // the database is resolved before anything is read, so a busy or closed one leaves the
+    // chunk unread instead of counting games that were never upserted
+    let db = get_db(ingest.db)?;
+    let mut db = db
+        .try_borrow_mut()
+        .map_err(|_| DatabaseError::busy(ingest.db))?;
+    let games = read(&mut ingest.reader);
+    db.upsert_games(games)?;
+    to_json(ingest.reader.progress())
+}
+
+/// Drops an ingest that won't be finished, e.g. after a failed `feedIngest` or once its
+/// database is closed. The games upserted so far stay in place.
+#[no_mangle]
+pub extern "C" fn abortIngest(ingest: u32) {
+    report(get_ingest(ingest).and_then(|entry| {
+        if entry.try_borrow_mut().is_err() {
+            return Err(DatabaseError::busy(ingest));
+        }
+        registry().borrow_mut().ingests.remove(&ingest);
+        Ok(())
+    }));
+}
+
+/// Takes the raw JSON of a Steam review histogram for the game `id`, see
+/// `import::steam::parse_histogram`.
+#[no_mangle]
+pub extern "C" fn importSteamHistogram(handle: u32, id: u32, ptr: js_value::Pointer) {
+    report(String::read_from_js(ptr).and_then(|json| {
+        let recommendations = parse_histogram(&json)?;
+        with_db(handle, |db| db.set_recommendations(id, recommendations))
+    }));
+}
+
+/// Upserts the games of a CSV catalog. `mappingPtr` holds a `CsvMapping` as JSON, or
+/// nothing for the defaults. Responds with the rows that were rejected.
+#[no_mangle]
+pub extern "C" fn importCsv(handle: u32, ptr: js_value::Pointer, mappingPtr: js_value::Pointer) {
+    respond(import_csv(handle, ptr, mappingPtr));
+}
+fn import_csv(
+    handle: u32,
+    ptr: js_value::Pointer,
+    mappingPtr: js_value::Pointer,
+) -> DatabaseResult<String> {
+    let csv = String::read_from_js(ptr)?;
+    let mapping = String::read_from_js(mappingPtr)?;
+    let mapping = if mapping.is_empty() {
+        CsvMapping::default()
+    } else {
+        serde_json::from_str::<CsvMapping>(&mapping).map_err(DatabaseError::parse)?
+    };
+    let import = parse_catalog(&csv, &mapping)?;
+    with_db(handle, |db| {
+        db.upsert_games(import.games)?;
+        to_json(&import.errors)
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn deleteGames(handle: u32, ptr: js_value::Pointer) {
+    respond(delete_games(handle, ptr));
+}
+fn delete_games(handle: u32, ptr: js_value::Pointer) -> DatabaseResult<String> {
+    let json = String::read_from_js(ptr)?;
+    let ids = serde_json::from_str::<Vec<u32>>(&json).map_err(DatabaseError::parse)?;
+    with_db(handle, |db| to_json(&db.delete_games(&ids)))
+}
+
+#[no_mangle]
+pub extern "C" fn updateUsers(handle: u32, ptr: js_value::Pointer) {
+    report(update_users(handle, ptr));
+}
+fn update_users(handle: u32, ptr: js_value::Pointer) -> DatabaseResult<()> {
+    let json = String::read_from_js(ptr)?;
+    let users = serde_json::from_str::<Vec<User>>(&json).map_err(DatabaseError::parse)?;
+    with_db(handle, |db| {
+        db.upsert_users(users);
+        Ok(())
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn begin(handle: u32) {
+    report(with_db(handle, |db| db.begin()));
+}
+
+#[no_mangle]
+pub extern "C" fn commit(handle: u32) {
+    report(with_db(handle, |db| db.commit()));
+}
+
+#[no_mangle]
+pub extern "C" fn rollback(handle: u32) {
+    report(with_db(handle, |db| db.rollback()));
+}
+
+/// See `Database::set_cache_capacity`.
+#[no_mangle]
+pub extern "C" fn setCacheCapacity(handle: u32, capacity: usize) {
+    report(with_db(handle, |db| {
+        db.set_cache_capacity(capacity);
+        Ok(())
+    }));
+}
+
+/// Responds with the ids of the dropped tags.
+#[no_mangle]
+pub extern "C" fn compactTags(handle: u32) {
+    respond(with_db(handle, |db| to_json(&db.compact_tags())));
+}
+
+#[derive(Deserialize)]
+struct TagEditInput {
+    from: String,
+    to: String,
+}
+
+fn read_tag_edit_input(ptr: js_value::Pointer) -> DatabaseResult<TagEditInput> {
+    let json = String::read_from_js(ptr)?;
+    serde_json::from_str::<TagEditInput>(&json).map_err(DatabaseError::parse)
+}
+
+#[no_mangle]
+pub extern "C" fn renameTag(handle: u32, ptr: js_value::Pointer) {
+    respond(
+        read_tag_edit_input(ptr)
+            .and_then(|input| with_db(handle, |db| to_json(&db.rename_tag(&input.from, input.to)))),
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn mergeTags(handle: u32, ptr: js_value::Pointer) {
+    respond(
+        read_tag_edit_input(ptr).and_then(|input| {
+            with_db(handle, |db| to_json(&db.merge_tags(&input.from, &input.to)))
+        }),
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn filterGames(
+    handle: u32,
+    idQueryInputs: js_value::Pointer,
+    rcmQueryInputs: js_value::Pointer,
+    sortInputs: js_value::Pointer,
+) {
+    respond(filter_games(
+        handle,
+        idQueryInputs,
+        rcmQueryInputs,
+        sortInputs,
+    ));
+}
+fn filter_games(
+    handle: u32,
+    idQueryInputs: js_value::Pointer,
+    rcmQueryInputs: js_value::Pointer,
+    sortInputs: js_value::Pointer,
+) -> DatabaseResult<String> {
+    let query = read_game_query(idQueryInputs, rcmQueryInputs, sortInputs)?;
+    read_db(handle, |db| to_json(&db.filter(&query)?))
+}
+fn read_game_query(
+    idQueryInputs: js_value::Pointer,
+    rcmQueryInputs: js_value::Pointer,
+    sortInputs: js_value::Pointer,
+) -> DatabaseResult<GameQuery> {
+    let mut query = GameQuery::new();
+    let idQueryInputs = IdQueryInput::read_many_from_js(idQueryInputs)?;
+    let rcmQueryInputs = RecommendationQueryInput::read_many_from_js(rcmQueryInputs)?;
+    let sortInputs = SortInput::read_many_from_js(sortInputs)?;
+    query.idQuery.build(idQueryInputs.into_iter());
+    query
+        .recommendationQuery
+        .build(rcmQueryInputs.into_iter())?;
+    query.sortQuery.build(sortInputs.into_iter());
+    Ok(query)
+}
+
+/// Like `filterGames`, but responds with the `QueryExplain` of the query instead of its
+/// results.
+#[no_mangle]
+pub extern "C" fn explainGames(
+    handle: u32,
+    idQueryInputs: js_value::Pointer,
+    rcmQueryInputs: js_value::Pointer,
+    sortInputs: js_value::Pointer,
+) {
+    respond(
+        read_game_query(idQueryInputs, rcmQueryInputs, sortInputs)
+            .and_then(|query| read_db(handle, |db| to_json(&db.explain(&query, host_clock)?))),
+    );
+}
+
+/// Like `filterGames`, but responds with the results written as the `ExportOptions` JSON
+/// at `optionsPtr` asks, or as a JSON array of the default columns if it is empty.
+#[no_mangle]
+pub extern "C" fn exportGames(
+    handle: u32,
+    idQueryInputs: js_value::Pointer,
+    rcmQueryInputs: js_value::Pointer,
+    sortInputs: js_value::Pointer,
+    optionsPtr: js_value::Pointer,
+) {
+    respond(export_games(
+        handle,
+        idQueryInputs,
+        rcmQueryInputs,
+        sortInputs,
+        optionsPtr,
+    ));
+}
+fn export_games(
+    handle: u32,
+    idQueryInputs: js_value::Pointer,
+    rcmQueryInputs: js_value::Pointer,
+    sortInputs: js_value::Pointer,
+    optionsPtr: js_value::Pointer,
+) -> DatabaseResult<String> {
+    let query = read_game_query(idQueryInputs, rcmQueryInputs, sortInputs)?;
+    let options = String::read_from_js(optionsPtr)?;
+    let options = if options.is_empty() {
+        ExportOptions::default()
+    } else {
+        serde_json::from_str::<ExportOptions>(&options).map_err(DatabaseError::parse)?
+    };
+    read_db(handle, |db| db.export(&query, &options))
+}
+
+/// Decodes a query once, for `runQuery`, and returns its handle. Returns 0 if the query
+/// could not be read. Takes the same inputs as `filterGames`.
+#[no_mangle]
+pub extern "C" fn prepareQuery(
+    idQueryInputs: js_value::Pointer,
+    rcmQueryInputs: js_value::Pointer,
+    sortInputs: js_value::Pointer,
+) -> u32 {
+    hand_out(read_game_query(idQueryInputs, rcmQueryInputs, sortInputs).and_then(open_query))
+}
+
+/// Runs the prepared query at `query` against the database at `handle`. `rcmParams` are
+/// recommendation inputs that replace the prepared ones of the same kind and window, e.g.
+/// to move a date window; they stay in place for later runs. Pass an empty list to run
+/// the query as it is.
+#[no_mangle]
+pub extern "C" fn runQuery(handle: u32, query: u32, rcmParams: js_value::Pointer) {
+    respond(run_query(handle, query, rcmParams));
+}
+fn run_query(handle: u32, query: u32, rcmParams: js_value::Pointer) -> DatabaseResult<String> {
+    let params = RecommendationQueryInput::read_many_from_js(rcmParams)?;
+    let prepared = get_query(query)?;
+    let mut prepared = prepared
+        .try_borrow_mut()
+        .map_err(|_| DatabaseError::busy(query))?;
+    prepared.bind(params.into_iter())?;
+    read_db(handle, |db| to_json(&db.filter(&prepared)?))
+}
+
+#[no_mangle]
+pub extern "C" fn releaseQuery(query: u32) {
+    report(get_query(query).and_then(|prepared| {
+        if prepared.try_borrow_mut().is_err() {
+            return Err(DatabaseError::busy(query));
+        }
+        registry().borrow_mut().queries.remove(&query);
+        Ok(())
+    }));
+}
@@ -1,5 +1,5 @@
+use crate::error::DatabaseResult;
 use std::fmt::Debug;
-use std::iter::FromIterator;
 use std::mem;
 use std::ptr;
 
@@ -14,9 +14,9 @@ where
     Self::Liner: Sized + Debug,
 {
     type Liner;
-    fn from_liner(input: Self::Liner) -> Self;
+    fn from_liner(input: Self::Liner) -> DatabaseResult<Self>;
 
-    fn read_from_js(pointer: js_value::Pointer) -> Self {
+    fn read_from_js(pointer: js_value::Pointer) -> DatabaseResult<Self> {
         let pointer = pointer.cast::<Self::Liner>();
         let mut liner: Option<Self::Liner> = None;
         unsafe {
@@ -26,12 +26,11 @@ where
         }
         Self::from_liner(liner.unwrap())
     }
-    fn read_many_from_js(ptr: js_value::Pointer) -> Vec<Self> {
-        Vec::from_iter(
-            Vec::<js_value::Pointer>::read_from_js(ptr)
-                .into_iter()
-                .map(Self::read_from_js),
-        )
+    fn read_many_from_js(ptr: js_value::Pointer) -> DatabaseResult<Vec<Self>> {
+        Vec::<js_value::Pointer>::read_from_js(ptr)?
+            .into_iter()
+            .map(Self::read_from_js)
+            .collect()
     }
 }
 
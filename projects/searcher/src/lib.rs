@@ -91,12 +91,12 @@ pub extern "C" fn convert(ptr: *mut c_void) -> *const c_void {
 #[no_mangle]
 pub extern "C" fn accumulate(ptr: *mut c_void, min: u32, max: u32) -> *const c_void {
     let histogram: &Histogram = read(ptr);
-    let mut up = 0;
-    let mut down = 0;
+    let mut up: u64 = 0;
+    let mut down: u64 = 0;
     for r in &histogram.rollups {
         if r.date >= min && r.date <= max {
-            up += r.recommendations_up;
-            down += r.recommendations_down;
+            up += r.recommendations_up as u64;
+            down += r.recommendations_down as u64;
         }
     }
     resolve((up, down))